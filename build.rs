@@ -104,14 +104,14 @@ fn main() {
         let file_name = resource.file_name();
         let fname = file_name.to_str().unwrap();
         if fname.ends_with(".bmp") {
-            bmps.push(Entry{filename: fname.to_string(), bigname: bigname});
+            bmps.push(Entry{filename: fname.to_string(), bigname});
         }
     }
 
     //create source file
     resources_out.write_all(b"
 mod resources {
-    #[derive(Debug, PartialEq, Eq, Hash, Clone)]
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
     pub enum Sprites {
     ").unwrap();
         for e in &bmps {
@@ -135,7 +135,7 @@ mod resources {
     use sdl2::video::Window;
     use sdl2::render::Canvas;
     impl Resources {
-        pub fn new(canvas: &Canvas<Window>) -> Result<Resources, Box<std::error::Error>> {
+        pub fn new(canvas: &Canvas<Window>) -> Result<Resources, Box<dyn std::error::Error>> {
             let mut r = Resources{sprites: Vec::new()};").unwrap();
     for e in &bmps {
         resources_out.write_fmt(format_args!("
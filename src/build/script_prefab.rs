@@ -6,34 +6,38 @@ macro_rules! script_prefab (
         [Components]
         $($comp:ident: $comp_type:ty = $comp_default:expr;)*
         [Behaviour]
-        fn new($new_vars:ident, $new_world:ident) $new_function:block
-        fn update($update_vars:ident, $update_world:ident) $update_function:block
+        fn new($new_vars:ident, $new_world:ident $(, $new_entity:ident)?) $new_function:block
+        fn update($update_vars:ident, $update_world:ident $(, $update_entity:ident)?) $update_function:block
     }} => {
-        #[derive(Clone)]
+        #[derive(Clone, serde_derive::Serialize, serde_derive::Deserialize)]
         pub struct $class_name {
             $(pub $var: $var_type),*
         }
         impl Component for $class_name {
             type Storage = VecStorage<Self>;
         }
-        #[allow(unused)]
+        #[allow(unused, clippy::new_ret_no_self)]
         impl $class_name {
-            pub fn init(w: &mut GameState) {
-                w.register_component::<$class_name>();
+            pub fn init(w: &mut GameState, schedule: &mut Schedule) {
+                ::homemade::prefab::register::<$class_name>(w, stringify!($class_name)); //vars get saved along with everything else
+                schedule.add_system(stringify!($class_name), Stage::Update, Self::update);
             }
             pub fn new($new_world: &GameState) -> Entity {
-                let e = $new_world.create_entity();
-                $new_world.insert(e, $class_name{$($var: $var_default),*});
-                $(
-                    $new_world.insert(e, $comp_default);
-                )*
-                $new_world.run(|($new_vars, $($comp),*): (&mut $class_name, $(&mut $comp_type),*)| {
+                let builder = $new_world.spawn();
+                let e = builder.entity();
+                //set up on the stack first so nothing's locked while the script runs
+                let mut state: ($class_name, $($comp_type),*) = ($class_name{$($var: $var_default),*}, $($comp_default),*);
+                {
+                    $(let $new_entity = e;)? //optional third argument, the script's own entity, i.e. for set_parent
+                    let (ref mut $new_vars, $(ref mut $comp),*) = state;
                     $new_function
-                });
-                e
+                }
+                let ($new_vars, $($comp),*) = state;
+                builder.with($new_vars)$(.with($comp))*.build()
             }
             pub fn update($update_world: &GameState) {
-                $update_world.run(|($update_vars, $($comp),*): (&mut $class_name, $(&mut $comp_type),*)| {
+                $update_world.query::<(&mut $class_name, $(&mut $comp_type),*)>().for_each_entity(|entity, ($update_vars, $($comp),*)| {
+                    $(let $update_entity = entity;)?
                     $update_function
                 });
             }
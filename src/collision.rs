@@ -0,0 +1,218 @@
+//axis-aligned boxes that push each other apart, as the last thing run_physics does once everything's moved
+//broad phase: every box goes into each grid cell it touches, and only boxes sharing a cell get compared
+//narrow phase: overlapping pairs get pushed apart along whichever axis they overlap least on,
+//and lose (or bounce back) whatever velocity they had going into each other
+//every contact goes out as a Collision event, so gameplay can react to it
+
+use std::collections::{HashMap, BTreeSet};
+use world::{GameState, Component, Entity};
+use world::storage::VecStorage;
+use serde_derive::{Serialize, Deserialize};
+use crate::common::{Position, Velocity};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)] //so prefabs only have to give what's different, i.e. "Collider": {"width": 32.0, "height": 32.0}
+pub struct Collider {
+    pub width: f64,
+    pub height: f64,
+    pub offset_x: f64, //from Position to the box's top-left corner
+    pub offset_y: f64,
+    pub fixed: bool, //walls and such; never moved by collisions, and two fixed ones never collide
+    pub layers: u32, //bits for what this is
+    pub mask: u32, //and for what it bumps into; both sides have to want it
+    pub restitution: f64, //0 stops dead against things, 1 bounces straight back
+}
+impl Component for Collider {
+    type Storage = VecStorage<Self>;
+}
+impl Collider {
+    pub fn new(width: f64, height: f64) -> Collider {
+        Collider{width, height, ..Collider::default()}
+    }
+    fn bumps_into(&self, other: &Collider) -> bool {
+        self.layers & other.mask != 0 && other.layers & self.mask != 0 && !(self.fixed && other.fixed)
+    }
+}
+impl Default for Collider {
+    fn default() -> Collider {
+        Collider{width: 16.0, height: 16.0, offset_x: 0.0, offset_y: 0.0, fixed: false, layers: 1, mask: !0, restitution: 0.0}
+    }
+}
+
+//a and b touched this frame, a being the older entity; normal points from a to b
+#[derive(Clone, Debug, PartialEq)]
+pub struct Collision {
+    pub a: Entity,
+    pub b: Entity,
+    pub normal: (f64, f64),
+}
+
+const CELL_SIZE: f64 = 64.0;
+
+//everything the narrow phase needs, copied out so nothing's locked while it's being worked out
+struct Body {
+    entity: Entity,
+    collider: Collider,
+    x: f64, //of the box, not the Position
+    y: f64,
+    velocity: Option<(f64, f64)>,
+    moved: bool,
+}
+impl Body {
+    fn cells(&self) -> impl Iterator<Item=(i64, i64)> {
+        let cell = |v: f64| (v / CELL_SIZE).floor() as i64;
+        let (x0, x1) = (cell(self.x), cell(self.x + self.collider.width));
+        let (y0, y1) = (cell(self.y), cell(self.y + self.collider.height));
+        (x0..=x1).flat_map(move |cx| (y0..=y1).map(move |cy| (cx, cy)))
+    }
+}
+
+pub fn resolve_collisions(w: &GameState) {
+    let mut bodies = Vec::new();
+    w.query::<(&Position, &Collider, Option<&Velocity>)>().for_each_entity(|entity, (pos, collider, vel)| {
+        bodies.push(Body{
+            entity,
+            x: pos.x + collider.offset_x,
+            y: pos.y + collider.offset_y,
+            collider: collider.clone(),
+            velocity: vel.map(|v| (v.x, v.y)),
+            moved: false,
+        });
+    });
+    bodies.sort_by_key(|b| b.entity);
+
+    let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (i, body) in bodies.iter().enumerate() {
+        for cell in body.cells() {
+            grid.entry(cell).or_default().push(i);
+        }
+    }
+    //big boxes can share more than one cell with the same thing, hence the set
+    let mut pairs = BTreeSet::new();
+    for cell in grid.values() {
+        for (n, &i) in cell.iter().enumerate() {
+            for &j in &cell[n + 1..] {
+                if bodies[i].collider.bumps_into(&bodies[j].collider) {
+                    pairs.insert((i, j));
+                }
+            }
+        }
+    }
+
+    for (i, j) in pairs {
+        if let Some(normal) = separate(&mut bodies, i, j) {
+            w.send(Collision{a: bodies[i].entity, b: bodies[j].entity, normal});
+        }
+    }
+
+    for body in bodies.iter().filter(|b| b.moved) {
+        let (x, y) = (body.x - body.collider.offset_x, body.y - body.collider.offset_y);
+        w.update(body.entity, |pos: &mut Position| *pos = Position{x, y});
+        if let Some((vx, vy)) = body.velocity {
+            w.update(body.entity, |vel: &mut Velocity| *vel = Velocity{x: vx, y: vy});
+        }
+    }
+}
+
+//pushes i and j apart if they overlap (all the way onto whichever isn't fixed, or half each), and says which way
+fn separate(bodies: &mut [Body], i: usize, j: usize) -> Option<(f64, f64)> {
+    let (a, b) = (&bodies[i], &bodies[j]);
+    let overlap_x = (a.x + a.collider.width).min(b.x + b.collider.width) - a.x.max(b.x);
+    let overlap_y = (a.y + a.collider.height).min(b.y + b.collider.height) - a.y.max(b.y);
+    if overlap_x <= 0.0 || overlap_y <= 0.0 {
+        return None;
+    }
+    //along the shallower axis, from a's centre towards b's
+    let (normal, depth) = if overlap_x < overlap_y {
+        let side = if a.x + a.collider.width / 2.0 <= b.x + b.collider.width / 2.0 { 1.0 } else { -1.0 };
+        ((side, 0.0), overlap_x)
+    } else {
+        let side = if a.y + a.collider.height / 2.0 <= b.y + b.collider.height / 2.0 { 1.0 } else { -1.0 };
+        ((0.0, side), overlap_y)
+    };
+    let (share_a, share_b) = match (a.collider.fixed, b.collider.fixed) {
+        (false, true) => (1.0, 0.0),
+        (true, false) => (0.0, 1.0),
+        _ => (0.5, 0.5),
+    };
+    push(&mut bodies[i], normal, -depth * share_a);
+    push(&mut bodies[j], normal, depth * share_b);
+    Some(normal)
+}
+
+//moves it by distance along normal, and takes away any velocity it had going the other way
+fn push(body: &mut Body, (nx, ny): (f64, f64), distance: f64) {
+    if distance == 0.0 {
+        return;
+    }
+    body.x += nx * distance;
+    body.y += ny * distance;
+    body.moved = true;
+    if let Some((vx, vy)) = &mut body.velocity {
+        //how fast it was heading into whatever it hit
+        let into = -(*vx * nx + *vy * ny) * distance.signum();
+        if into > 0.0 {
+            let change = into * (1.0 + body.collider.restitution) * distance.signum();
+            *vx += nx * change;
+            *vy += ny * change;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::{Schedule, Events};
+    use crate::common::{self, Friction, PhysicsBundle};
+    use crate::time::Time;
+
+    fn prepare_world() -> (GameState, Schedule<'static>) {
+        let mut w = GameState::new();
+        let mut schedule = Schedule::new();
+        w.set_resource(Time::new(1.0)); //so velocities are per step
+        common::init(&mut w, &mut schedule);
+        (w, schedule)
+    }
+
+    fn mover(w: &GameState, x: f64, vx: f64) -> Entity {
+        w.spawn().with_bundle(PhysicsBundle{
+            position: Position{x, y: 0.0},
+            velocity: Velocity{x: vx, y: 0.0},
+            friction: Friction{x: 1.0, y: 1.0},
+        }).build()
+    }
+
+    #[test]
+    fn walls_stop_things() {
+        let (mut w, mut schedule) = prepare_world();
+        let wall = w.spawn().with(Position{x: 20.0, y: -50.0}).with(Collider{fixed: true, ..Collider::new(10.0, 100.0)}).build();
+        let ball = mover(&w, 0.0, 8.0);
+        w.insert(ball, Collider::new(16.0, 16.0));
+        let ghost = mover(&w, 0.0, 8.0);
+        w.insert(ghost, Collider{layers: 2, mask: 2, ..Collider::new(16.0, 16.0)});
+        schedule.run(&mut w);
+        //moved to 8, 4 into the wall, and pushed back out
+        assert_eq!(w.get_value::<Position>(ball).x, 4.0);
+        assert_eq!(w.get_value::<Velocity>(ball).x, 0.0);
+        //not on the wall's layer, so it goes straight through
+        assert_eq!(w.get_value::<Position>(ghost).x, 8.0);
+        let hits: Vec<_> = w.resource::<Events<Collision>>().iter().cloned().collect();
+        assert_eq!(hits, vec![Collision{a: wall, b: ball, normal: (-1.0, 0.0)}]);
+    }
+
+    #[test]
+    fn bodies_push_each_other() {
+        let (mut w, mut schedule) = prepare_world();
+        let a = mover(&w, 0.0, 4.0);
+        w.insert(a, Collider{restitution: 1.0, ..Collider::new(16.0, 16.0)});
+        let b = mover(&w, 20.0, -4.0);
+        w.insert(b, Collider::new(16.0, 16.0));
+        schedule.run(&mut w);
+        //4 and 16 after moving, so 4 apart each way, and they split it
+        assert_eq!(w.get_value::<Position>(a).x, 2.0);
+        assert_eq!(w.get_value::<Position>(b).x, 18.0);
+        //a bounces, b just stops
+        assert_eq!(w.get_value::<Velocity>(a).x, -4.0);
+        assert_eq!(w.get_value::<Velocity>(b).x, 0.0);
+    }
+}
@@ -1,34 +1,53 @@
 //some common components (VecStorage-level common)
+//the physics ones live in archetype tables, so run_physics only walks entities that actually move
+//everything's per second (friction too), and run_physics scales it by Time::step, so the step rate doesn't change how things move
 
 extern crate world;
-use world::{GameState, Component, SystemRunner};
-use world::storage::{VecStorage, HashMapStorage};
+use std::collections::{HashMap, HashSet};
+use world::{GameState, Component, Entity, Schedule, Stage, With, Parent, Bundle, EntityBuilder, Events, Resource};
+use world::storage::{VecStorage, ArchetypeStorage};
+use serde_derive::{Serialize, Deserialize};
+use crate::prefab;
+use crate::collision::{self, Collider, Collision};
+use crate::time::{self, Time};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Position {
     pub x: f64,
     pub y: f64,
 }
 impl Component for Position {
+    type Storage = ArchetypeStorage<Self>;
+}
+
+//optional: an offset from the parent's Position, for things that are stuck to something
+//propagate_positions keeps Position (which is still what everything else reads) up to date from it
+//without a Parent it doesn't do anything
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LocalPosition {
+    pub x: f64,
+    pub y: f64,
+}
+impl Component for LocalPosition {
     type Storage = VecStorage<Self>;
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Velocity {
     pub x: f64,
     pub y: f64,
 }
 impl Component for Velocity {
-    type Storage = HashMapStorage<Self>;
+    type Storage = ArchetypeStorage<Self>;
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Acceleration {
 	pub x: f64,
 	pub y: f64,
 }
 impl Component for Acceleration {
-	type Storage = HashMapStorage<Self>;
+	type Storage = ArchetypeStorage<Self>;
 }
 
 
@@ -36,49 +55,175 @@ impl Component for Acceleration {
 //i.e. "entity steps on ice" -> insert(entity, Friction(0.5))
 //     "entity steps off ice" -> remove::<Friction>(entity)
 //UNLESS the entity has it explicitly set (i.e. it's hovering; f=1.0)
-#[derive(Clone)]
+//it's how much of its velocity something keeps after a whole second, so 0.5 halves it every second
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Friction {
 	pub x: f64,
 	pub y: f64,
 }
 impl Component for Friction {
-	type Storage = HashMapStorage<Self>;
+	type Storage = ArchetypeStorage<Self>;
 }
 
 
-#[allow(unused)]
+//everything run_physics needs to move something around
+pub struct PhysicsBundle {
+    pub position: Position,
+    pub velocity: Velocity,
+    pub friction: Friction,
+}
+impl Bundle for PhysicsBundle {
+    fn add_to(self, builder: EntityBuilder<'_>) -> EntityBuilder<'_> {
+        builder.with(self.position).with(self.velocity).with(self.friction)
+    }
+}
+
 pub fn run_physics(w: &GameState) {
-	w.run(|(vel, acc): (&mut Velocity, &mut Acceleration)| {
-		vel.x += acc.x;
-		vel.y += acc.y;
+	let dt = w.resource::<Time>().step;
+	w.query::<(&mut Velocity, &Acceleration)>().for_each(|(vel, acc)| {
+		vel.x += acc.x * dt;
+		vel.y += acc.y * dt;
 	});
-	w.run(|(pos, vel): (&mut Position, &mut Velocity)| {
-		pos.x += vel.x;
-		pos.y += vel.y;
+	w.query::<(&mut Position, &Velocity)>().for_each(|(pos, vel)| {
+		pos.x += vel.x * dt;
+		pos.y += vel.y * dt;
 	});
-	w.update_all(|i, vel: &mut Velocity| {
-		if let Some(fric) = w.clone::<Friction>(i) {
-			vel.x *= fric.x;
-			vel.y *= fric.y;
+	w.query::<(&mut Velocity, Option<&Friction>)>().for_each(|(vel, fric)| {
+		if let Some(fric) = fric {
+			vel.x *= fric.x.powf(dt);
+			vel.y *= fric.y.powf(dt);
 		} else {
 			vel.x = 0.0; //implicitly halting friction if not specified otherwise
 			vel.y = 0.0;
 		}
 	});
+	//anything that ended up inside something else gets pushed back out, see collision.rs
+	collision::resolve_collisions(w);
 	//note: in things that contain stats, max velocity is determined by Dexterity
 }
 
-//for indexing
-#[derive(Clone)]
-pub struct Name(pub &'static str);
-impl Component for Name {
-    type Storage = VecStorage<Self>;
+//parent's Position plus LocalPosition, all the way up
+fn world_position(w: &GameState, e: Entity) -> Option<Position> {
+    offset_from_parents(w, e, &mut HashSet::new())
+}
+//stops wherever it's been before, in case the hierarchy's been put back in a loop without the hooks knowing
+fn offset_from_parents(w: &GameState, e: Entity, seen: &mut HashSet<Entity>) -> Option<Position> {
+    if !seen.insert(e) {
+        return None;
+    }
+    let local = w.clone::<LocalPosition>(e)?;
+    let parent = w.parent(e)?;
+    let base = offset_from_parents(w, parent, seen).or_else(|| w.clone::<Position>(parent))?;
+    Some(Position{x: base.x + local.x, y: base.y + local.y})
+}
+
+//after physics, so attached things end up wherever their parents moved to this frame
+pub fn propagate_positions(w: &GameState) {
+    let mut attached = Vec::new();
+    w.query::<(&LocalPosition, With<Parent>)>().for_each_entity(|e, _| attached.push(e));
+    for e in attached {
+        if let Some(pos) = world_position(w, e) {
+            //only touched if it actually moved, so it doesn't show up as Changed (i.e. to the spatial hash) every step
+            let mut current = None;
+            w.read(e, |p: &Position| current = Some((p.x, p.y)));
+            match current {
+                Some(xy) if xy == (pos.x, pos.y) => (),
+                Some(_) => w.update(e, |p: &mut Position| *p = pos.clone()),
+                None => w.insert(e, pos), //first time, so it needs one
+            }
+        }
+    }
 }
 
-pub fn init(w: &mut GameState) {
-    w.register_component::<Position>();
-    w.register_component::<Velocity>();
-    w.register_component::<Acceleration>();
-    w.register_component::<Friction>();
-    w.register_component::<Name>();
-}
\ No newline at end of file
+//every Position as of the start of the current step, for drawing things between steps
+#[derive(Default)]
+pub struct PreviousPositions(HashMap<Entity, (f64, f64)>);
+impl Resource for PreviousPositions {}
+
+pub fn remember_positions(w: &GameState) {
+    let mut previous = w.resource_mut::<PreviousPositions>();
+    previous.0.clear();
+    w.read_all(|e, pos: &Position| {
+        previous.0.insert(e, (pos.x, pos.y));
+    });
+}
+
+//where to draw e, which is currently at pos: Time::alpha of the way there from where it was a step ago
+//anything that's only just got a Position is drawn right where it is
+pub fn interpolate(w: &GameState, e: Entity, pos: &Position) -> Position {
+    let alpha = w.resource::<Time>().alpha;
+    match w.resource::<PreviousPositions>().0.get(&e) {
+        Some(&(x, y)) => Position{x: x + (pos.x - x) * alpha, y: y + (pos.y - y) * alpha},
+        None => pos.clone(),
+    }
+}
+
+//in world, so GameState can look things up by it, see find_by_name
+pub use world::Name;
+
+pub fn init(w: &mut GameState, schedule: &mut Schedule) {
+    prefab::register::<Position>(w, "Position");
+    prefab::register::<Velocity>(w, "Velocity");
+    prefab::register::<Acceleration>(w, "Acceleration");
+    prefab::register::<Friction>(w, "Friction");
+    prefab::register::<LocalPosition>(w, "LocalPosition");
+    prefab::register::<Name>(w, "Name");
+    prefab::register::<Parent>(w, "Parent"); //Children get rebuilt from these
+    prefab::register::<Collider>(w, "Collider");
+    w.add_event::<Collision>();
+    time::init(w);
+    w.set_resource(PreviousPositions::default());
+    schedule.add_system("remember_positions", Stage::PreUpdate, remember_positions)
+        .reads::<Position>()
+        .writes::<PreviousPositions>();
+    schedule.add_system("physics", Stage::PostUpdate, run_physics)
+        .access::<(&mut Position, &mut Velocity, &Acceleration, &Friction, &Collider)>()
+        .reads::<Time>()
+        .writes::<Events<Collision>>();
+    schedule.add_system("propagate_positions", Stage::PostUpdate, propagate_positions)
+        .access::<(&mut Position, &LocalPosition, &Parent)>()
+        .after("physics");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepare_world() -> (GameState, Schedule<'static>) {
+        let mut w = GameState::new();
+        let mut schedule = Schedule::new();
+        w.set_resource(Time::new(1.0)); //so velocities are per step
+        init(&mut w, &mut schedule);
+        (w, schedule)
+    }
+
+    #[test]
+    fn attached_positions() {
+        let (mut w, mut schedule) = prepare_world();
+        let cart = w.spawn().with_bundle(PhysicsBundle{
+            position: Position{x: 10.0, y: 0.0},
+            velocity: Velocity{x: 1.0, y: 0.0},
+            friction: Friction{x: 1.0, y: 1.0},
+        }).build();
+        let horse = w.create_entity();
+        w.insert(horse, LocalPosition{x: 16.0, y: 0.0});
+        w.set_parent(horse, cart);
+        let rider = w.create_entity();
+        w.insert(rider, LocalPosition{x: 0.0, y: -8.0});
+        w.set_parent(rider, horse);
+        schedule.run(&mut w);
+        let pos = w.get_value::<Position>(rider);
+        assert_eq!((pos.x, pos.y), (27.0, -8.0));
+        //a loop with no Position anywhere in it just doesn't get one
+        let (a, b) = (w.create_entity(), w.create_entity());
+        w.insert(a, LocalPosition{x: 1.0, y: 1.0});
+        w.insert(b, LocalPosition{x: 1.0, y: 1.0});
+        w.insert_without_hooks(a, Parent(b));
+        w.insert_without_hooks(b, Parent(a));
+        assert!(world_position(&w, a).is_none());
+        //and they go with it too
+        w.delete_entity(cart);
+        schedule.run(&mut w);
+        assert!(w.is_deleted(horse) && w.is_deleted(rider));
+    }
+}
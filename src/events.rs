@@ -0,0 +1,31 @@
+//gameplay events, so modules can react to each other without calling into each other directly
+//i.e. inventory says an item's buffs should go on, stats is the one that actually does it
+
+use world::{GameState, Entity};
+use crate::stats::Stat;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuffEvent {
+    Added {
+        entity: Entity,
+        stat: Stat,
+        source: String,
+        amount: i32,
+    },
+    Removed {
+        entity: Entity,
+        source: String,
+    },
+}
+
+//after it's been used up, right before the item gets deleted
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemConsumed {
+    pub entity: Entity,
+    pub item: Entity,
+}
+
+pub fn init(w: &mut GameState) {
+    w.add_event::<BuffEvent>();
+    w.add_event::<ItemConsumed>();
+}
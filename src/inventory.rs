@@ -1,13 +1,22 @@
-use world::{GameState, Entity, Component};
-use world::storage::{VecStorage, HashMapStorage};
-use crate::stats;
+use world::{GameState, Entity, Component, Parent};
+use world::storage::{HashMapStorage, SparseSetStorage};
 use crate::stats::Stat;
 use crate::common::{Name, Position};
+use crate::events::{BuffEvent, ItemConsumed};
+use crate::prefab;
+use serde_derive::{Serialize, Deserialize};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Inventory {
     pub items: Vec<Entity>,
 }
+//the item's side of Inventory::items, i.e. who's holding it
+//add_item/remove_item keep this in sync; the hooks on it are what turn ActiveEffect buffs on and off
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InInventory(pub Entity);
+impl Component for InInventory {
+    type Storage = SparseSetStorage<Self>;
+}
 impl Component for Inventory {
     type Storage = HashMapStorage<Self>;
 }
@@ -16,15 +25,20 @@ impl Inventory {
         Inventory{items: Vec::new()}
     }
 }
+impl Default for Inventory {
+    fn default() -> Inventory {
+        Inventory::new()
+    }
+}
 
 //3 things items can be, specifically, so far :3
 //right-click menu: Use, Equip
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Consumable {
     buffs: Vec<(Stat, i32)>,
 }
 impl Component for Consumable {
-    type Storage = VecStorage<Self>;
+    type Storage = SparseSetStorage<Self>;
 }
 impl Consumable {
     pub fn new(buffs: Vec<(Stat, i32)>) -> Consumable {
@@ -114,12 +128,12 @@ pub fn unequip<T: Component>(w: &GameState, equipper: Index, item: Index) {
 */
 
 //buffs applied/removed UPON adding/removing to inventory
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ActiveEffect {
     buffs: Vec<(Stat, i32)>,
 }
 impl Component for ActiveEffect {
-    type Storage = VecStorage<Self>;
+    type Storage = SparseSetStorage<Self>;
 }
 impl ActiveEffect {
     pub fn new(buffs: Vec<(Stat, i32)>) -> ActiveEffect {
@@ -127,93 +141,247 @@ impl ActiveEffect {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Stackable {
     quantity: u32,
 }
 impl Component for Stackable {
-    type Storage = VecStorage<Self>;
+    type Storage = SparseSetStorage<Self>;
 }
 
 pub fn init(w: &mut GameState) {
-    w.register_component::<Inventory>();
-    w.register_component::<Consumable>();
-    w.register_component::<ActiveEffect>();
-    w.register_component::<Stackable>();
+    prefab::register_with(w, "Inventory", |_| Ok(Inventory::new())); //always starts out empty
+    prefab::register::<Consumable>(w, "Consumable");
+    prefab::register::<ActiveEffect>(w, "ActiveEffect");
+    prefab::register::<Stackable>(w, "Stackable");
+    prefab::register::<InInventory>(w, "InInventory");
+    w.on_insert::<InInventory>(effects_on);
+    w.on_replace::<InInventory>(effects_off); //handed straight to someone else
+    w.on_remove::<InInventory>(effects_off); //taken out, or deleted while still held
+}
+
+//ActiveEffect buffs apply for exactly as long as the item's in somebody's inventory
+fn effects_on(w: &GameState, item: Entity, &InInventory(entity): &InInventory) {
+    if let Some(a) = w.clone::<ActiveEffect>(item) {
+        if let Some(Name(name)) = w.clone(item) {
+            for &(stat, amount) in &a.buffs {
+                w.send(BuffEvent::Added{entity, stat, source: name.clone(), amount});
+            }
+        } else {
+            panic!("ActiveEffect items must have Name"); //TODO: not this
+            //maybe add a 'description' field to ActiveEffect and Consumable
+        }
+    }
+}
+fn effects_off(w: &GameState, item: Entity, &InInventory(entity): &InInventory) {
+    if w.has_flag::<ActiveEffect>(item) {
+        if let Some(Name(name)) = w.clone(item) {
+            w.send(BuffEvent::Removed{entity, source: name});
+        } else {
+            panic!("ActiveEffect items must have Name"); //enforce this rule
+        }
+    }
+}
+
+//items are the same kind if they're called the same thing and have the same components,
+//not counting the Position they might still have from lying on the ground, or what says who's holding them
+fn same_kind(w: &GameState, a: Entity, b: Entity) -> bool {
+    let (mut a_type, mut b_type) = (w.type_of(a), w.type_of(b));
+    for id in [w.component_id::<Position>(), w.component_id::<InInventory>(), w.component_id::<Parent>()] {
+        a_type.remove(id);
+        b_type.remove(id);
+    }
+    a_type == b_type && w.clone::<Name>(a) == w.clone::<Name>(b)
 }
 
 pub fn add_item(w: &GameState, entity: Entity, item: Entity) {
+    let (mut pushed, mut stacked) = (false, false);
     w.update(entity, |inv: &mut Inventory| {
-        if w.has_flag::<Position>(item) {
-            w.delete::<Position>(item);
+        //already held; it'd only end up stacked onto itself
+        if inv.items.contains(&item) {
+            return;
         }
         if w.has_flag::<Stackable>(item) {
-            let mut has_stack = false;
-            for &e in &inv.items {
-                if w.type_of(e) == w.type_of(item) {
-                    has_stack = true;
-                    w.update(e, |stack: &mut Stackable| {
-                        stack.quantity += 1;
-                    });
-                    break;
-                }
-            }
-            if !has_stack {
-                inv.items.push(item);
+            if let Some(&stack) = inv.items.iter().find(|&&e| same_kind(w, e, item)) {
+                let quantity = w.get_value::<Stackable>(item).quantity;
+                w.update(stack, |s: &mut Stackable| s.quantity += quantity);
+                stacked = true;
+                return;
             }
-        } else {
-            inv.items.push(item);
         }
-
-        w.update(item, |a: &mut ActiveEffect| {
-            if let Some(Name(name)) = w.clone(item) {
-                for buff in &a.buffs {
-                    stats::buff(w, entity, buff.0, name, buff.1);
-                }
-            } else {
-                panic!("ActiveEffect items must have Name"); //TODO: not this
-                //maybe add a 'description' field to ActiveEffect and Consumable
-            }
-        });
+        inv.items.push(item);
+        pushed = true;
     });
+    //it's part of the stack now, so there's nothing left of it to hold onto
+    if stacked {
+        w.commands().delete_entity(item);
+        return;
+    }
+    //nowhere to put it, so it stays where it is
+    if !pushed {
+        return;
+    }
+    //deferred, in case whoever's picking this up is in the middle of going through positions
+    if w.has_flag::<Position>(item) {
+        w.commands().delete::<Position>(item);
+    }
+    w.insert(item, InInventory(entity));
+    w.set_parent(item, entity); //so it goes wherever its owner goes, including being deleted
 }
 
-pub fn remove_item(w: &GameState, entity: Entity, item: Entity) {
+//takes one of item out (dropping it wherever entity is); a stack only actually leaves once the last one's gone
+//returns whether item itself came out
+pub fn remove_item(w: &GameState, entity: Entity, item: Entity) -> bool {
+    let mut removed = false;
     w.update(entity, |inv: &mut Inventory| {
-        for i in 0..inv.items.len() {
-            if inv.items[i] == item {
-                if w.has_flag::<Stackable>(item) {
-                    w.update(inv.items[i], |stack: &mut Stackable| {
-                        stack.quantity -= 1;
-                    });
-                } else {
-                    inv.items.remove(i);
+        if let Some(i) = inv.items.iter().position(|&e| e == item) {
+            let mut last = true;
+            w.update(item, |stack: &mut Stackable| {
+                last = stack.quantity <= 1;
+                if !last {
+                    stack.quantity -= 1;
+                }
+            });
+            if last {
+                inv.items.remove(i);
+                if let Some(pos) = w.clone::<Position>(entity) {
+                    w.commands().insert(item, pos);
                 }
-                w.update(item, |_: &mut ActiveEffect| {
-                    if let Some(Name(name)) = w.clone(item) {
-                        stats::unbuff(w, entity, name);
-                    } else {
-                        panic!("ActiveEffect items must have Name"); //enforce this rule
-                    }
-                });
+                removed = true;
             }
         }
-        if let Some(pos) = w.clone::<Position>(entity) {
-            w.insert(item, pos);
-        }
     });
+    if removed {
+        w.delete::<InInventory>(item);
+        w.remove_parent(item);
+    }
+    removed
 }
 
 pub fn consume(w: &GameState, entity: Entity, item: Entity) {
     w.update(item, |c: &mut Consumable| {
         if let Some(Name(name)) = w.clone(item) {
-            remove_item(w, entity, item); //take out of inventory first (removes ActiveEffect)
-            for buff in &c.buffs {
-                stats::buff(w, entity, buff.0, name, buff.1);
+            //take out of inventory first (removes ActiveEffect); the rest of a stack stays put
+            let last = remove_item(w, entity, item);
+            for &(stat, amount) in &c.buffs {
+                w.send(BuffEvent::Added{entity, stat, source: name.clone(), amount});
+            }
+            w.send(ItemConsumed{entity, item});
+            if last {
+                w.commands().delete_entity(item); //this actually deletes it from the world, at the next update_entities
             }
         } else {
             panic!("Consumable items must have Name");
         }
-        w.delete_entity(item); //this actually deletes it from the world, supposedly
     });
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::{Schedule, Events};
+    use crate::{common, events, stats};
+
+    fn prepare_world() -> (GameState, Schedule<'static>) {
+        let mut w = GameState::new();
+        let mut schedule = Schedule::new();
+        events::init(&mut w);
+        common::init(&mut w, &mut schedule);
+        stats::init(&mut w, &mut schedule);
+        init(&mut w);
+        (w, schedule)
+    }
+
+    #[test]
+    fn buffs_follow_items() {
+        let (mut w, mut schedule) = prepare_world();
+        let p = w.create_entity();
+        w.insert(p, Inventory::new());
+        stats::set_base(&w, p, stats::VITALITY, 32);
+        let e = w.create_entity();
+        w.insert(e, Name::new("Inventory Test Entity"));
+        w.insert(e, Position{x: 200.0, y: 300.0});
+        w.insert(e, Consumable::new(vec![(stats::VITALITY, 3)]));
+        w.insert(e, ActiveEffect::new(vec![(stats::VITALITY, -3)]));
+        w.update_entities();
+
+        add_item(&w, p, e);
+        schedule.run(&mut w);
+        assert_eq!(w.get_value::<Inventory>(p).items, vec![e]);
+        assert!(!w.has_flag::<Position>(e));
+        assert_eq!(stats::get_max(&w, p, stats::VITALITY), 29);
+
+        consume(&w, p, e);
+        schedule.run(&mut w);
+        assert!(w.get_value::<Inventory>(p).items.is_empty());
+        assert_eq!(stats::get_max(&w, p, stats::VITALITY), 35);
+        assert!(!w.is_alive(e));
+        let consumed: Vec<_> = w.resource::<Events<ItemConsumed>>().iter().cloned().collect();
+        assert_eq!(consumed, vec![ItemConsumed{entity: p, item: e}]);
+    }
+
+    #[test]
+    fn deleted_items_lose_effects() {
+        let (mut w, mut schedule) = prepare_world();
+        let p = w.create_entity();
+        w.insert(p, Inventory::new());
+        stats::set_base(&w, p, stats::STRENGTH, 10);
+        let e = w.create_entity();
+        w.insert(e, Name::new("Cursed Ring"));
+        w.insert(e, ActiveEffect::new(vec![(stats::STRENGTH, -4)]));
+        add_item(&w, p, e);
+        assert_eq!(w.clone::<InInventory>(e), Some(InInventory(p)));
+        schedule.run(&mut w);
+        assert_eq!(stats::get_max(&w, p, stats::STRENGTH), 6);
+        w.delete_entity(e);
+        schedule.run(&mut w); //swept at the end of the first stage, buff comes off in PostUpdate
+        assert_eq!(stats::get_max(&w, p, stats::STRENGTH), 10);
+    }
+
+    #[test]
+    fn stacks() {
+        let (mut w, mut schedule) = prepare_world();
+        let p = w.spawn().with(Inventory::new()).with(Position{x: 5.0, y: 5.0}).build();
+        stats::set_base(&w, p, stats::VITALITY, 20);
+        let potion = || w.spawn()
+            .with(Name::new("potion"))
+            .with(Position{x: 0.0, y: 0.0})
+            .with(Stackable{quantity: 1})
+            .with(ActiveEffect::new(vec![(stats::VITALITY, 1)]))
+            .build();
+        let (a, b, c) = (potion(), potion(), potion());
+        add_item(&w, p, a);
+        add_item(&w, p, b);
+        schedule.run(&mut w);
+        assert_eq!(w.get_value::<Inventory>(p).items, vec![a]);
+        assert_eq!(w.get_value::<Stackable>(a).quantity, 2);
+        assert!(!w.is_alive(b));
+        //only the one that's actually held counts
+        assert_eq!(stats::get_max(&w, p, stats::VITALITY), 21);
+        //picking up what's already held does nothing
+        add_item(&w, p, a);
+        schedule.run(&mut w);
+        assert_eq!(w.get_value::<Stackable>(a).quantity, 2);
+        assert!(w.is_alive(a));
+        //and neither does picking something up without an Inventory to put it in
+        add_item(&w, a, c);
+        schedule.run(&mut w);
+        assert_eq!(w.get_value::<Position>(c).x, 0.0);
+        assert_eq!((w.clone::<InInventory>(c), w.parent(c)), (None, None));
+
+        //one off the top, and the stack's still held
+        assert!(!remove_item(&w, p, a));
+        schedule.run(&mut w);
+        assert_eq!(w.get_value::<Stackable>(a).quantity, 1);
+        assert_eq!(w.clone::<InInventory>(a), Some(InInventory(p)));
+        assert!(!w.has_flag::<Position>(a));
+        assert_eq!(stats::get_max(&w, p, stats::VITALITY), 21);
+        //the last one
+        assert!(remove_item(&w, p, a));
+        schedule.run(&mut w);
+        assert!(w.get_value::<Inventory>(p).items.is_empty());
+        assert_eq!(w.parent(a), None);
+        assert_eq!(w.get_value::<Position>(a).x, 5.0);
+        assert_eq!(stats::get_max(&w, p, stats::VITALITY), 20);
+    }
+}
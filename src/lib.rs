@@ -1,3 +1,10 @@
 pub mod stats;
 pub mod inventory;
 pub mod common;
+pub mod events;
+pub mod prefab;
+pub mod save;
+pub mod snapshot;
+pub mod spatial;
+pub mod collision;
+pub mod time;
@@ -1,19 +1,24 @@
 //use crate::combat::{Weapon};
 
-use world::{GameState, SystemRunner, Entity, Component};
-use world::storage::{VecStorage, BTreeMapStorage};
+use world::{GameState, Component, Resource, Schedule, Stage};
+use world::storage::{VecStorage, NullStorage};
 use homemade::common;
-use homemade::common::{Name, Position, Velocity, Friction};
+use homemade::common::{Name, Position, Velocity, Friction, PhysicsBundle};
 use homemade::inventory;
 use homemade::stats;
+use homemade::prefab;
+use homemade::spatial;
+use homemade::collision::Collider;
+use homemade::time::FixedTimestep;
+use serde_derive::{Serialize, Deserialize};
 use std::error::Error;
 use resources::{Resources, Sprites};
 use scripts::*;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct Player;
 impl Component for Player {
-    type Storage = BTreeMapStorage<Self>;
+    type Storage = NullStorage<Self>;
 }
 
 /*mod combat {
@@ -49,25 +54,32 @@ fn follow(distance: f64, leader: &Position, follower: &mut Position) {
 
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct Enemy;
 impl Component for Enemy {
-    type Storage = BTreeMapStorage<Self>;
+    type Storage = NullStorage<Self>;
 }
 
+//where the mouse is, for drawing the cursor; set from the event pump every frame
+struct Cursor {
+    x: i32,
+    y: i32,
+}
+impl Resource for Cursor {}
+
 //include all the static resources from codegen
 include!(concat!(env!("OUT_DIR"), "/resources.rs"));
 include!(concat!(env!("OUT_DIR"), "/scripts.rs"));
 
 //inject these into the engine renderer initialization code
 //invariant: make the engine run with or without these, since renderer is supposed to be independent
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct RenderInfo(Sprites);
 impl Component for RenderInfo {
     type Storage = VecStorage<Self>;
 }
 
-fn main() -> Result<(), Box<Error>> {
+fn main() -> Result<(), Box<dyn Error>> {
     let sdl_context = sdl2::init()?;
     let video = sdl_context.video()?;
     let window = video.window("rust-sdl2 demo", 640, 400)
@@ -85,35 +97,53 @@ fn main() -> Result<(), Box<Error>> {
 
     let mut r = Resources::new(&canvas)?;
     let mut w = GameState::new();
-
-    w.register_component::<RenderInfo>();
-
-    w.register_component::<Enemy>();
-    w.register_component::<Player>();
-    common::init(&mut w);
-    stats::init(&mut w);
+    let mut schedule = Schedule::new();
+
+    prefab::register::<RenderInfo>(&mut w, "RenderInfo");
+    w.add_index::<RenderInfo, Sprites>(|RenderInfo(sprite)| sprite.clone()); //i.e. w.lookup::<RenderInfo, _>(&Sprites::Enemy)
+
+    prefab::register::<Enemy>(&mut w, "Enemy");
+    prefab::register::<Player>(&mut w, "Player");
+    w.set_resource(Cursor{x: 0, y: 0});
+    homemade::events::init(&mut w);
+    common::init(&mut w, &mut schedule);
+    spatial::init(&mut w, &mut schedule);
+    stats::init(&mut w, &mut schedule);
     inventory::init(&mut w);
-    SpinPlayer::init(&mut w);
-    EnemyTrail::init(&mut w);
-    Follower::init(&mut w);
+    SpinPlayer::init(&mut w, &mut schedule);
+    EnemyTrail::init(&mut w, &mut schedule);
+    Follower::init(&mut w, &mut schedule);
+    //walls just off the edges of the screen, to keep the player on it
+    for (x, y, width, height) in [(-32.0, -32.0, 704.0, 32.0), (-32.0, 400.0, 704.0, 32.0), (-32.0, 0.0, 32.0, 400.0), (640.0, 0.0, 32.0, 400.0)] {
+        w.spawn().with(Position{x, y}).with(Collider{fixed: true, ..Collider::new(width, height)}).build();
+    }
     //w.register_component::<Weapon>();
     //w.register_component::<Equippable<Weapon>>(); //containee
     //w.register_component::<Equipment<Weapon>>(); //container
-    let p = w.create_entity();
-    w.insert(p, Player);
-    w.insert(p, Position{x: 0.0, y: 0.0});
-    w.insert(p, Velocity{x: 2.0, y: 2.0});
-    w.insert(p, Friction{x: 1.0, y: 1.0});
-    w.insert(p, Name("kay"));
-    w.insert(p, RenderInfo(Sprites::Player));
+    let p = w.spawn()
+        .with(Player)
+        .with_bundle(PhysicsBundle{
+            position: Position{x: 0.0, y: 0.0},
+            velocity: Velocity{x: 120.0, y: 120.0},
+            friction: Friction{x: 1.0, y: 1.0},
+        })
+        .with(Collider{restitution: 1.0, ..Collider::new(32.0, 32.0)}) //bounces off the walls
+        .with(Name::new("kay"))
+        .with(RenderInfo(Sprites::Player))
+        .with(inventory::Inventory::new())
+        .build();
     stats::set_base(&w, p, stats::VITALITY, 32);
-    w.insert(p, inventory::Inventory::new());
+
+    //everything in prefabs/, see prefab.rs
+    prefab::load_dir(&w, concat!(env!("CARGO_MANIFEST_DIR"), "/prefabs"))?;
+    let slime = prefab::spawn_prefab(&w, "slime");
+    w.insert(slime, Position{x: 400.0, y: 200.0});
 
     //w.insert(p, Equipment::<Weapon>::new(3));
     /*let sword = w.create_entity();
     w.insert(sword, Weapon{damage: 2, range: 1});
     w.insert(sword, Equippable::<Weapon>::new());
-    w.insert(sword, Name("the flaming raging poisoning sword of doom"));
+    w.insert(sword, Name::new("the flaming raging poisoning sword of doom"));
     w.insert(p, Inventory::new());
     w.update(p, |inv: &mut Inventory|{
         inv.add_item(&w, sword);
@@ -128,60 +158,13 @@ fn main() -> Result<(), Box<Error>> {
     println!("{:?}", w.get_value::<Equipment<Weapon>>(p).equipment());
     */
 
-    //TODO: move this into 'tests' mod of 'inventory'
-    let e = w.create_entity();
-    w.insert(e, RenderInfo(Sprites::Enemy));
-    w.insert(e, Name("Inventory Test Entity"));
-    w.insert(e, Position{x: 200.0, y: 300.0});
-    w.insert(e, inventory::Consumable::new(vec![(stats::VITALITY, 3)]));
-    w.insert(e, inventory::ActiveEffect::new(vec![(stats::VITALITY, -3)]));
-    w.update_entities();
-    println!("type hash: {:#0128b}", w.type_of(e));
-    inventory::add_item(&w, p, e);
-    println!("{:?}", w.get_value::<inventory::Inventory>(p).items);
-    println!("should be 29: {}", stats::get_max(&w, p, stats::VITALITY));
-    inventory::consume(&w, p, e);
-    println!("{:?}", w.get_value::<inventory::Inventory>(p).items);
-    println!("should be 35: {}", stats::get_max(&w, p, stats::VITALITY));
-
-    SpinPlayer::new(&mut w);
-    EnemyTrail::new(&mut w);
+    SpinPlayer::new(&w);
+    EnemyTrail::new(&w);
     
-    println!("こんにしわ! starting main loop");
-    let mut event_pump = sdl_context.event_pump()?;
-    'running: loop {
-        //parse events
-        use sdl2::event::Event;
-        use sdl2::keyboard::Keycode;
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit{..} |
-                Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
-                    break 'running
-                },
-                _ => {}
-            }
-        }
-
-
-
-        SpinPlayer::update(&w);
-        EnemyTrail::update(&w);
-        
-        //using new `run` SystemRunner syntax
-        w.run(|(_, pos, vel): (&mut Player, &mut Position, &mut Velocity)| {
-            if pos.x + 32.0 > 640.0 || pos.x < 0.0 {
-                vel.x *= -1.0;
-            }
-            if pos.y + 32.0 > 400.0 || pos.y < 0.0 {
-                vel.y *= -1.0;
-            }
-        });
-
-        common::run_physics(&w);
-        
+    //sdl's canvas has to stay on the main thread
+    schedule.add_local_system("render", Stage::Render, |w| {
         //rendering system :3
-        //TODO: animation system, render according to seconds 
+        //TODO: animation system, render according to seconds (Time::elapsed)
         // maybe store a start_time on every .reset() and then do current_frame = (seconds_passed - start_time) % num_frames;
         //TODO: rendering system, render according to physical units and not pixels
         use sdl2::rect::Rect;
@@ -194,9 +177,10 @@ fn main() -> Result<(), Box<Error>> {
         canvas.set_draw_color(Color::RGB(60, 44, 56));
         let _ = canvas.fill_rect(None);
 
-        w.update_all(|e, &mut Position{x, y}| {
+        w.read_all(|e, pos: &Position| {
+            let Position{x, y} = common::interpolate(w, e, pos); //between the last two steps, so movement's smooth whatever the refresh rate
             let mut rect = Rect::new(x as i32, y as i32, 16, 16);
-            w.update(e, |RenderInfo(info)| {
+            w.read(e, |RenderInfo(info)| {
                 match info {
                     Sprites::Enemy => {
                         r[Sprites::Enemy].set_alpha_mod(127);
@@ -229,11 +213,33 @@ fn main() -> Result<(), Box<Error>> {
         lighting.set_blend_mode(BlendMode::Mod);
         let _ = canvas.copy(&lighting, None, None);
 
-        let _ = canvas.copy(&r[Sprites::Cursor], None, Rect::new(event_pump.mouse_state().x(), event_pump.mouse_state().y(), 16, 16));
+        let cursor = w.resource::<Cursor>();
+        let _ = canvas.copy(&r[Sprites::Cursor], None, Rect::new(cursor.x, cursor.y, 16, 16));
 
         canvas.present();
+    });
+
+    println!("こんにしわ! starting main loop");
+    let mut event_pump = sdl_context.event_pump()?;
+    let mut timestep = FixedTimestep::new(60.0);
+    'running: loop {
+        //parse events
+        use sdl2::event::Event;
+        use sdl2::keyboard::Keycode;
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit{..} |
+                Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
+                    break 'running
+                },
+                _ => {}
+            }
+        }
+
+        *w.resource_mut::<Cursor>() = Cursor{x: event_pump.mouse_state().x(), y: event_pump.mouse_state().y()};
 
-        w.update_entities();
+        //scripts and physics however many times are due (60 a second), then rendering; update_entities runs after each stage
+        timestep.run(&mut w, &mut schedule);
         //std::thread::sleep(std::time::Duration::from_secs(2));
     }
 
@@ -0,0 +1,201 @@
+//prefabs: entities described in json instead of code, i.e. prefabs/enemy.json
+//  {"Name": "enemy", "Position": {"x": 10.0, "y": 20.0}, "Stats": {"VITALITY": 12}}
+//each key is a component name, as given to register(); each value is whatever that component deserializes from
+//everything's parsed when it's loaded, so a typo shows up then and not halfway through spawning something
+//the same registry is what save.rs and snapshot.rs go by, so anything registered here gets saved too
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use world::{GameState, Component, Entity, EntityBuilder, Resource};
+use crate::snapshot::{self, Rows};
+
+//one parsed component, ready to be cloned onto as many entities as need it
+pub(crate) trait Part: Send + Sync {
+    fn add_to<'w>(&self, builder: EntityBuilder<'w>) -> EntityBuilder<'w>;
+}
+impl<C: Component> Part for C {
+    fn add_to<'w>(&self, builder: EntityBuilder<'w>) -> EntityBuilder<'w> {
+        builder.with(self.clone())
+    }
+}
+
+type Parser = Box<dyn Fn(Value) -> Result<Box<dyn Part>, serde_json::Error> + Send + Sync>;
+
+//everything that can be done with one registered component
+pub(crate) struct Registered {
+    pub(crate) id: usize, //w.component_id(), so snapshots can tell what isn't registered
+    parse: Parser, //for prefabs
+    pub(crate) save: fn(&GameState, Entity) -> Option<Result<Value, serde_json::Error>>,
+    pub(crate) load: fn(Value) -> Result<Box<dyn Part>, serde_json::Error>, //for saves, no special cases
+    pub(crate) snapshot: fn(&GameState) -> bincode::Result<Vec<u8>>,
+    pub(crate) restore: fn(&[u8]) -> bincode::Result<Box<dyn Rows>>,
+}
+
+fn save_component<C: Component + Serialize>(w: &GameState, e: Entity) -> Option<Result<Value, serde_json::Error>> {
+    w.clone::<C>(e).map(serde_json::to_value)
+}
+fn load_component<C: Component + DeserializeOwned>(v: Value) -> Result<Box<dyn Part>, serde_json::Error> {
+    Ok(Box::new(serde_json::from_value::<C>(v)?))
+}
+
+//the registry: component names to what to do with them, and every prefab loaded so far
+//lives in the world as a resource; register() sets it up if nobody has yet
+pub struct Prefabs {
+    pub(crate) components: HashMap<&'static str, Registered>,
+    prefabs: HashMap<String, Vec<Box<dyn Part>>>,
+}
+impl Resource for Prefabs {}
+
+#[derive(Debug)]
+pub enum PrefabError {
+    Io(String, std::io::Error),
+    Json(String, serde_json::Error), //not even an object of components
+    UnknownComponent(String, String), //(prefab, component), i.e. it was never register()ed
+    BadComponent(String, String, serde_json::Error), //(prefab, component, what's wrong with it)
+    UnknownPrefab(String),
+    NothingRegistered(String), //(prefab), i.e. it was loaded before anything was register()ed
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrefabError::Io(path, e) => write!(f, "can't read {}: {}", path, e),
+            PrefabError::Json(prefab, e) => write!(f, "prefab {} isn't valid: {}", prefab, e),
+            PrefabError::UnknownComponent(prefab, c) => write!(f, "prefab {} has a {}, which isn't registered", prefab, c),
+            PrefabError::BadComponent(prefab, c, e) => write!(f, "prefab {} has a bad {}: {}", prefab, c, e),
+            PrefabError::UnknownPrefab(prefab) => write!(f, "no prefab named {}", prefab),
+            PrefabError::NothingRegistered(prefab) => write!(f, "can't load prefab {}, no components have been registered", prefab),
+        }
+    }
+}
+
+impl std::error::Error for PrefabError {}
+
+fn prefabs(w: &mut GameState) -> world::borrow::StorageMut<'_, Prefabs> {
+    if !w.has_resource::<Prefabs>() {
+        w.set_resource(Prefabs{components: HashMap::new(), prefabs: HashMap::new()});
+    }
+    w.resource_mut::<Prefabs>()
+}
+
+//for components that deserialize the way they're written down
+pub fn register<C: Component + Serialize + DeserializeOwned>(w: &mut GameState, name: &'static str) {
+    register_with(w, name, serde_json::from_value::<C>);
+}
+//for ones that need some help in prefabs, i.e. Stats, which only takes base values there
+//(saves still go through serde as normal)
+pub fn register_with<C: Component + Serialize + DeserializeOwned>(w: &mut GameState, name: &'static str, parse: fn(Value) -> Result<C, serde_json::Error>) {
+    w.register_component::<C>();
+    let id = w.component_id::<C>();
+    prefabs(w).components.insert(name, Registered{
+        id,
+        parse: Box::new(move |v| parse(v).map(|c| Box::new(c) as Box<dyn Part>)),
+        save: save_component::<C>,
+        load: load_component::<C>,
+        snapshot: snapshot::take_rows::<C>,
+        restore: snapshot::parse_rows::<C>,
+    });
+}
+
+//adds (or replaces) one prefab
+pub fn load(w: &GameState, name: &str, json: &str) -> Result<(), PrefabError> {
+    let components: HashMap<String, Value> = serde_json::from_str(json).map_err(|e| PrefabError::Json(name.to_string(), e))?;
+    let mut prefabs = w.try_resource_mut::<Prefabs>().map_err(|_| PrefabError::NothingRegistered(name.to_string()))?;
+    let mut parts = Vec::new();
+    for (component, value) in components {
+        let registered = match prefabs.components.get(component.as_str()) {
+            Some(registered) => registered,
+            None => return Err(PrefabError::UnknownComponent(name.to_string(), component)),
+        };
+        match (registered.parse)(value) {
+            Ok(part) => parts.push(part),
+            Err(e) => return Err(PrefabError::BadComponent(name.to_string(), component, e)),
+        }
+    }
+    prefabs.prefabs.insert(name.to_string(), parts);
+    Ok(())
+}
+//every .json file in a folder, named after the file
+pub fn load_dir(w: &GameState, dir: impl AsRef<Path>) -> Result<(), PrefabError> {
+    let dir = dir.as_ref();
+    let entries = std::fs::read_dir(dir).map_err(|e| PrefabError::Io(dir.display().to_string(), e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| PrefabError::Io(dir.display().to_string(), e))?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let json = std::fs::read_to_string(&path).map_err(|e| PrefabError::Io(path.display().to_string(), e))?;
+            load(w, &path.file_stem().unwrap().to_string_lossy(), &json)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn try_spawn_prefab(w: &GameState, name: &str) -> Result<Entity, PrefabError> {
+    //nothing registered means nothing loaded either
+    let prefabs = w.try_resource::<Prefabs>().map_err(|_| PrefabError::UnknownPrefab(name.to_string()))?;
+    match prefabs.prefabs.get(name) {
+        Some(parts) => Ok(parts.iter().fold(w.spawn(), |builder, part| part.add_to(builder)).build()),
+        None => Err(PrefabError::UnknownPrefab(name.to_string())),
+    }
+}
+pub fn spawn_prefab(w: &GameState, name: &str) -> Entity {
+    match try_spawn_prefab(w, name) {
+        Ok(e) => e,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::Schedule;
+    use crate::common::{self, Name, Position};
+    use crate::{stats, inventory};
+
+    fn prepare_world() -> GameState {
+        let mut w = GameState::new();
+        let mut schedule = Schedule::new();
+        common::init(&mut w, &mut schedule);
+        stats::init(&mut w, &mut schedule);
+        inventory::init(&mut w);
+        w
+    }
+
+    #[test]
+    fn spawn_from_json() {
+        let w = prepare_world();
+        load(&w, "potion", r#"{
+            "Name": "Inventory Test Entity",
+            "Position": {"x": 200.0, "y": 300.0},
+            "Consumable": {"buffs": [["VITALITY", 3]]},
+            "ActiveEffect": {"buffs": [["VITALITY", -3]]}
+        }"#).unwrap();
+        load(&w, "slime", r#"{"Name": "slime", "Stats": {"VITALITY": 12, "STRENGTH": 2}}"#).unwrap();
+        let a = spawn_prefab(&w, "potion");
+        let b = spawn_prefab(&w, "potion");
+        assert_ne!(a, b);
+        assert_eq!(w.get_value::<Name>(b).0, "Inventory Test Entity");
+        assert_eq!(w.get_value::<Position>(a).y, 300.0);
+        assert!(w.has_component::<inventory::Consumable>(a));
+        let slime = spawn_prefab(&w, "slime");
+        assert_eq!(stats::get_base(&w, slime, stats::STRENGTH), 2);
+        //hooks still run, so Health comes along with Vitality
+        assert_eq!(stats::get(&w, slime, stats::VITALITY), 12);
+    }
+
+    #[test]
+    fn bad_prefabs() {
+        let w = prepare_world();
+        assert!(matches!(load(&w, "a", "[1, 2]"), Err(PrefabError::Json(..))));
+        assert!(matches!(load(&w, "b", r#"{"Wings": {}}"#), Err(PrefabError::UnknownComponent(_, c)) if c == "Wings"));
+        assert!(matches!(load(&w, "c", r#"{"Position": {"x": 1.0}}"#), Err(PrefabError::BadComponent(_, c, _)) if c == "Position"));
+        assert!(matches!(try_spawn_prefab(&w, "c"), Err(PrefabError::UnknownPrefab(_))));
+        //before anything's registered at all
+        let w = GameState::new();
+        assert!(matches!(load(&w, "d", "{}"), Err(PrefabError::NothingRegistered(_))));
+        assert!(matches!(try_spawn_prefab(&w, "d"), Err(PrefabError::UnknownPrefab(_))));
+    }
+}
@@ -0,0 +1,195 @@
+//saving the whole world to json and loading it back
+//goes by the prefab registry: every component that's been prefab::register()ed gets saved, and nothing else does
+//entities come back with new handles; references to them inside components (Inventory.items, Parent, script vars)
+//are fixed up as they're loaded, see world::persist
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
+use world::{GameState, Entity, Parent, Error};
+use world::persist::remap_entities;
+use crate::prefab::Prefabs;
+
+#[derive(Serialize, Deserialize)]
+struct SavedEntity {
+    id: Entity, //what it was called when it was saved, so references to it can be matched up
+    components: BTreeMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedWorld {
+    entities: Vec<SavedEntity>,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Json(serde_json::Error), //not a save file
+    UnknownComponent(String), //it's in the file, but nobody's registered it (anymore)
+    BadComponent(String, serde_json::Error), //(component, what's wrong with it)
+    ParentCycle(Entity), //the saved entity ends up its own ancestor
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "can't read/write save: {}", e),
+            SaveError::Json(e) => write!(f, "save isn't valid: {}", e),
+            SaveError::UnknownComponent(c) => write!(f, "save has a {}, which isn't registered", c),
+            SaveError::BadComponent(c, e) => write!(f, "save has a bad {}: {}", c, e),
+            SaveError::ParentCycle(e) => write!(f, "save has {:?} as its own ancestor", e),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+//kept out of the builder, see load_world
+const PARENT: &str = "Parent";
+
+pub fn save_world(w: &GameState) -> Result<String, SaveError> {
+    let prefabs = w.resource::<Prefabs>();
+    let mut entities = Vec::new();
+    for id in w.entities() {
+        let mut components = BTreeMap::new();
+        for (&name, registered) in &prefabs.components {
+            if let Some(value) = (registered.save)(w, id) {
+                components.insert(name.to_string(), value.map_err(|e| SaveError::BadComponent(name.to_string(), e))?);
+            }
+        }
+        entities.push(SavedEntity{id, components});
+    }
+    serde_json::to_string_pretty(&SavedWorld{entities}).map_err(SaveError::Json)
+}
+
+//adds everything in the save to w (which usually wants to be fresh, with all the same init()s run)
+//and hands back the new entities, in the order they were saved
+pub fn load_world(w: &GameState, json: &str) -> Result<Vec<Entity>, SaveError> {
+    let saved: SavedWorld = serde_json::from_str(json).map_err(SaveError::Json)?;
+    let prefabs = w.resource::<Prefabs>();
+    //check first, so a bad save doesn't leave half an entity behind
+    for name in saved.entities.iter().flat_map(|e| e.components.keys()) {
+        if !prefabs.components.contains_key(name.as_str()) {
+            return Err(SaveError::UnknownComponent(name.clone()));
+        }
+    }
+    let ids: Vec<Entity> = saved.entities.iter().map(|e| e.id).collect();
+    let loaded: Vec<Entity> = ids.iter().map(|_| w.create_entity()).collect();
+    let map: HashMap<Entity, Entity> = ids.iter().cloned().zip(loaded.iter().cloned()).collect();
+    let undo = |e| {
+        for &entity in &loaded {
+            w.delete_entity(entity);
+        }
+        Err(e)
+    };
+    //everything gets parsed before any of it goes in
+    let parsed = remap_entities(map, || {
+        saved.entities.into_iter().map(|e| {
+            let (mut parts, mut parent) = (Vec::new(), None);
+            for (name, value) in e.components {
+                if name == PARENT {
+                    parent = Some(serde_json::from_value::<Parent>(value).map_err(|err| SaveError::BadComponent(name, err))?);
+                } else {
+                    parts.push((prefabs.components[name.as_str()].load)(value).map_err(|err| SaveError::BadComponent(name, err))?);
+                }
+            }
+            Ok((parts, parent))
+        }).collect::<Result<Vec<_>, _>>()
+    });
+    let parsed = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return undo(e),
+    };
+    //and each entity's hooks only go off once all of its components are in
+    for (&entity, (parts, _)) in loaded.iter().zip(&parsed) {
+        parts.iter().fold(w.build(entity), |builder, part| part.add_to(builder)).build();
+    }
+    //parents last, through try_set_parent, since nothing stops a save file going round in circles
+    for ((&entity, &id), (_, parent)) in loaded.iter().zip(&ids).zip(&parsed) {
+        if let Some(&Parent(parent)) = parent.as_ref() {
+            if let Err(Error::ParentCycle(_)) = w.try_set_parent(entity, parent) {
+                return undo(SaveError::ParentCycle(id));
+            }
+        }
+    }
+    Ok(loaded)
+}
+
+pub fn save_to_file(w: &GameState, path: impl AsRef<Path>) -> Result<(), SaveError> {
+    std::fs::write(path, save_world(w)?).map_err(SaveError::Io)
+}
+pub fn load_from_file(w: &GameState, path: impl AsRef<Path>) -> Result<Vec<Entity>, SaveError> {
+    load_world(w, &std::fs::read_to_string(path).map_err(SaveError::Io)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::Schedule;
+    use crate::common::{self, Name, Position};
+    use crate::inventory::{self, Inventory, ActiveEffect};
+    use crate::{events, stats};
+
+    fn prepare_world() -> (GameState, Schedule<'static>) {
+        let mut w = GameState::new();
+        let mut schedule = Schedule::new();
+        events::init(&mut w);
+        common::init(&mut w, &mut schedule);
+        stats::init(&mut w, &mut schedule);
+        inventory::init(&mut w);
+        (w, schedule)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (mut w, mut schedule) = prepare_world();
+        //leave a hole, so the indices don't line up
+        let gone = w.create_entity();
+        let p = w.spawn().with(Name::new("kay")).with(Position{x: 1.0, y: 2.0}).with(Inventory::new()).build();
+        stats::set_base(&w, p, stats::VITALITY, 32);
+        let ring = w.spawn().with(Name::new("ring")).with(ActiveEffect::new(vec![(stats::VITALITY, -2)])).build();
+        inventory::add_item(&w, p, ring);
+        schedule.run(&mut w);
+        stats::modify(&w, p, stats::VITALITY, -5);
+        w.delete_entity(gone);
+        w.update_entities();
+        let json = save_world(&w).unwrap();
+
+        let (mut w2, mut schedule2) = prepare_world();
+        let filler = w2.create_entity();
+        let loaded = load_world(&w2, &json).unwrap();
+        assert!(!loaded.contains(&filler));
+        let (p2, ring2) = (loaded[0], loaded[1]);
+        assert_eq!(w2.get_value::<Name>(p2).0, "kay");
+        assert_eq!(w2.get_value::<Position>(p2).y, 2.0);
+        assert_eq!(w2.get_value::<Inventory>(p2).items, vec![ring2]);
+        assert_eq!(w2.parent(ring2), Some(p2));
+        assert_eq!(w2.children(p2), vec![ring2]);
+        assert_eq!(stats::get_max(&w2, p2, stats::VITALITY), 30);
+        assert_eq!(stats::get(&w2, p2, stats::VITALITY), 25);
+        //buffs don't get doubled up by loading the item back into the inventory
+        schedule2.run(&mut w2);
+        assert_eq!(stats::get_max(&w2, p2, stats::VITALITY), 30);
+        //and it still knows how to take them off again
+        inventory::remove_item(&w2, p2, ring2);
+        schedule2.run(&mut w2);
+        assert_eq!(stats::get_max(&w2, p2, stats::VITALITY), 32);
+    }
+
+    #[test]
+    fn bad_saves() {
+        let (w, _) = prepare_world();
+        assert!(matches!(load_world(&w, "{}"), Err(SaveError::Json(_))));
+        let unknown = r#"{"entities": [{"id": [0, 0], "components": {"Wings": null}}]}"#;
+        assert!(matches!(load_world(&w, unknown), Err(SaveError::UnknownComponent(c)) if c == "Wings"));
+        assert!(w.entities().is_empty());
+        let bad = r#"{"entities": [{"id": [0, 0], "components": {"Name": "a"}}, {"id": [1, 0], "components": {"Position": 3}}]}"#;
+        assert!(matches!(load_world(&w, bad), Err(SaveError::BadComponent(c, _)) if c == "Position"));
+        assert!(w.entities().is_empty());
+        let cycle = r#"{"entities": [{"id": [0, 0], "components": {"Parent": [1, 0]}}, {"id": [1, 0], "components": {"Parent": [0, 0]}}]}"#;
+        assert!(matches!(load_world(&w, cycle), Err(SaveError::ParentCycle(e)) if e.id() == 1));
+        assert!(w.entities().is_empty());
+    }
+}
@@ -0,0 +1,365 @@
+//binary snapshots of the whole world, for save states, rewinding and rollback
+//a lot faster than save.rs, but only good for putting back into the same game (same init()s, same build):
+//entities keep their exact handles, nothing gets remapped, and no hooks run on the way back in
+//every component on every entity has to be prefab::register()ed, or there's no snapshot (it'd just be missing after a restore)
+//resources are left alone unless they're register_resource()ed, i.e. Time; buffered events get thrown out
+//take them between frames, i.e. right after Schedule::run, same as GameState::entity_table
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use world::{GameState, Component, Entity, EntityTable, Children, Resource};
+use crate::prefab::Prefabs;
+
+//bump this whenever what's written below changes, so old snapshots get turned away instead of misread
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+#[derive(Clone)]
+pub struct Snapshot {
+    bytes: Vec<u8>,
+}
+impl Snapshot {
+    //i.e. for writing it out, or sending it to the other players
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+    pub fn from_bytes(bytes: Vec<u8>) -> Snapshot {
+        Snapshot{bytes}
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    WrongVersion(u32), //made by some other build
+    Bincode(bincode::Error), //not a snapshot, or cut short
+    UnknownComponent(String), //it's in the snapshot, but nobody's registered it
+    BadComponent(String, bincode::Error), //(component, what's wrong with it)
+    UnregisteredComponent(&'static str), //an entity has one, so there's no snapshotting it
+    UnknownResource(String), //same as UnknownComponent, see register_resource
+    BadResource(String, bincode::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::WrongVersion(v) => write!(f, "snapshot is version {}, expected {}", v, SNAPSHOT_VERSION),
+            SnapshotError::Bincode(e) => write!(f, "snapshot isn't valid: {}", e),
+            SnapshotError::UnknownComponent(c) => write!(f, "snapshot has a {}, which isn't registered", c),
+            SnapshotError::BadComponent(c, e) => write!(f, "snapshot has a bad {}: {}", c, e),
+            SnapshotError::UnregisteredComponent(c) => write!(f, "can't snapshot a {}, it isn't registered", c),
+            SnapshotError::UnknownResource(r) => write!(f, "snapshot has a {} resource, which isn't registered", r),
+            SnapshotError::BadResource(r, e) => write!(f, "snapshot has a bad {} resource: {}", r, e),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+//one component's whole storage, parsed and ready to go back in
+pub(crate) trait Rows {
+    fn put_back(self: Box<Self>, w: &GameState);
+}
+impl<C: Component> Rows for Vec<(Entity, C)> {
+    fn put_back(self: Box<Self>, w: &GameState) {
+        for (e, c) in *self {
+            w.insert_without_hooks(e, c);
+        }
+    }
+}
+
+pub(crate) fn take_rows<C: Component + Serialize>(w: &GameState) -> bincode::Result<Vec<u8>> {
+    let mut rows = Vec::new();
+    w.read_all(|e, c: &C| rows.push((e, c.clone())));
+    rows.sort_by_key(|&(e, _)| e); //some storages (i.e. HashMapStorage) don't keep any particular order
+    bincode::serialize(&rows)
+}
+pub(crate) fn parse_rows<C: Component + DeserializeOwned>(bytes: &[u8]) -> bincode::Result<Box<dyn Rows>> {
+    Ok(Box::new(bincode::deserialize::<Vec<(Entity, C)>>(bytes)?))
+}
+
+//a resource that's missing when the snapshot's taken gets taken out again on restore
+pub(crate) trait SavedResource {
+    fn put_back(self: Box<Self>, w: &mut GameState);
+}
+impl<R: Resource> SavedResource for Option<R> {
+    fn put_back(self: Box<Self>, w: &mut GameState) {
+        match *self {
+            Some(r) => w.set_resource(r),
+            None => drop(w.remove_resource::<R>()),
+        }
+    }
+}
+
+fn take_resource<R: Resource + Serialize>(w: &GameState) -> bincode::Result<Vec<u8>> {
+    bincode::serialize(&w.try_resource::<R>().ok().as_deref())
+}
+fn parse_resource<R: Resource + DeserializeOwned>(bytes: &[u8]) -> bincode::Result<Box<dyn SavedResource>> {
+    Ok(Box::new(bincode::deserialize::<Option<R>>(bytes)?))
+}
+
+//by name, which keeps them in the same order every time
+#[derive(Default)]
+struct Resources(BTreeMap<&'static str, ResourceFns>);
+impl Resource for Resources {}
+struct ResourceFns {
+    snapshot: fn(&GameState) -> bincode::Result<Vec<u8>>,
+    restore: fn(&[u8]) -> bincode::Result<Box<dyn SavedResource>>,
+}
+
+//a resource that gets rolled back along with the entities, i.e. Time
+pub fn register_resource<R: Resource + Serialize + DeserializeOwned>(w: &mut GameState, name: &'static str) {
+    if !w.has_resource::<Resources>() {
+        w.set_resource(Resources::default());
+    }
+    w.resource_mut::<Resources>().0.insert(name, ResourceFns{snapshot: take_resource::<R>, restore: parse_resource::<R>});
+}
+
+//Children aren't registered (saves rebuild them from Parent), but without hooks nothing would rebuild them here
+const CHILDREN: &str = "Children";
+
+pub fn snapshot(w: &GameState) -> Result<Snapshot, SnapshotError> {
+    let prefabs = w.resource::<Prefabs>();
+    let mut covered: HashSet<usize> = prefabs.components.values().map(|r| r.id).collect();
+    covered.insert(w.component_id::<Children>());
+    for e in w.entities() {
+        if let Some(c) = w.components_of(e).into_iter().find(|c| !covered.contains(&c.id)) {
+            return Err(SnapshotError::UnregisteredComponent(c.name));
+        }
+    }
+    let mut components = vec![(CHILDREN.to_string(), take_rows::<Children>(w).map_err(|e| SnapshotError::BadComponent(CHILDREN.to_string(), e))?)];
+    //in name order, so the same world always makes the same bytes (i.e. for checking two players haven't drifted apart)
+    let mut registered: Vec<_> = prefabs.components.iter().collect();
+    registered.sort_by_key(|&(&name, _)| name);
+    for (&name, registered) in registered {
+        components.push((name.to_string(), (registered.snapshot)(w).map_err(|e| SnapshotError::BadComponent(name.to_string(), e))?));
+    }
+    let mut resources = Vec::new();
+    if let Ok(registered) = w.try_resource::<Resources>() {
+        for (&name, fns) in &registered.0 {
+            resources.push((name.to_string(), (fns.snapshot)(w).map_err(|e| SnapshotError::BadResource(name.to_string(), e))?));
+        }
+    }
+    //the version goes on its own up front, so it can be checked before trying to make sense of the rest
+    let mut bytes = bincode::serialize(&SNAPSHOT_VERSION).map_err(SnapshotError::Bincode)?;
+    bincode::serialize_into(&mut bytes, &(w.entity_table(), components, resources)).map_err(SnapshotError::Bincode)?;
+    Ok(Snapshot{bytes})
+}
+
+//takes w back to exactly how it was; anything created since is gone, and handles to it go stale
+pub fn restore(w: &mut GameState, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+    let mut bytes = snapshot.as_bytes();
+    let version: u32 = bincode::deserialize_from(&mut bytes).map_err(SnapshotError::Bincode)?;
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::WrongVersion(version));
+    }
+    type Parts = (EntityTable, Vec<(String, Vec<u8>)>, Vec<(String, Vec<u8>)>);
+    let (table, components, resources): Parts = bincode::deserialize_from(&mut bytes).map_err(SnapshotError::Bincode)?;
+    //everything's parsed before the world gets touched, same as loading a save
+    let rows = {
+        let prefabs = w.resource::<Prefabs>();
+        components.iter().map(|(name, bytes)| {
+            let parse: fn(&[u8]) -> bincode::Result<Box<dyn Rows>> = match prefabs.components.get(name.as_str()) {
+                Some(registered) => registered.restore,
+                None if name == CHILDREN => parse_rows::<Children>,
+                None => return Err(SnapshotError::UnknownComponent(name.clone())),
+            };
+            parse(bytes).map_err(|e| SnapshotError::BadComponent(name.clone(), e))
+        }).collect::<Result<Vec<_>, _>>()?
+    };
+    let saved_resources = {
+        let registered = w.try_resource::<Resources>().ok();
+        resources.iter().map(|(name, bytes)| {
+            let fns = registered.as_ref().and_then(|r| r.0.get(name.as_str()));
+            let fns = fns.ok_or_else(|| SnapshotError::UnknownResource(name.clone()))?;
+            (fns.restore)(bytes).map_err(|e| SnapshotError::BadResource(name.clone(), e))
+        }).collect::<Result<Vec<_>, _>>()?
+    };
+    w.restore_entity_table(&table);
+    for r in rows {
+        r.put_back(w);
+    }
+    for r in saved_resources {
+        r.put_back(w);
+    }
+    w.clear_events(); //i.e. buffs that were sent for frames that never happened now
+    Ok(())
+}
+
+//the last however many frames, i.e. for rewinding, or rolling back to the last frame everyone agreed on and resimulating from there
+//frame numbers are whatever the caller's counting; pushing one that's already in here (i.e. while resimulating)
+//replaces it and everything after it
+pub struct SnapshotRing {
+    frames: VecDeque<(u64, Snapshot)>,
+    capacity: usize,
+}
+impl Resource for SnapshotRing {}
+
+impl SnapshotRing {
+    pub fn new(capacity: usize) -> SnapshotRing {
+        assert!(capacity > 0, "SnapshotRing needs room for at least one frame");
+        SnapshotRing{frames: VecDeque::with_capacity(capacity), capacity}
+    }
+    pub fn push(&mut self, frame: u64, snapshot: Snapshot) {
+        while self.frames.back().is_some_and(|&(f, _)| f >= frame) {
+            self.frames.pop_back();
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back((frame, snapshot));
+    }
+    pub fn get(&self, frame: u64) -> Option<&Snapshot> {
+        self.frames.iter().find(|&&(f, _)| f == frame).map(|(_, s)| s)
+    }
+    pub fn latest(&self) -> Option<(u64, &Snapshot)> {
+        self.frames.back().map(|(f, s)| (*f, s))
+    }
+    //how far back it's possible to go
+    pub fn oldest(&self) -> Option<u64> {
+        self.frames.front().map(|&(f, _)| f)
+    }
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use world::Schedule;
+    use world::storage::VecStorage;
+    use crate::common::{self, Name, Position, Velocity, Friction, PhysicsBundle};
+    use crate::time::{FixedTimestep, Time};
+    use crate::inventory::{self, Inventory, ActiveEffect};
+    use crate::{events, stats};
+
+    fn prepare_world() -> (GameState, Schedule<'static>) {
+        let mut w = GameState::new();
+        let mut schedule = Schedule::new();
+        events::init(&mut w);
+        common::init(&mut w, &mut schedule);
+        stats::init(&mut w, &mut schedule);
+        inventory::init(&mut w);
+        (w, schedule)
+    }
+
+    #[test]
+    fn rollback() {
+        let (mut w, mut schedule) = prepare_world();
+        let p = w.spawn().with(Name::new("kay")).with(Position{x: 1.0, y: 2.0}).with(Inventory::new()).build();
+        stats::set_base(&w, p, stats::VITALITY, 32);
+        let ring = w.spawn().with(Name::new("ring")).with(ActiveEffect::new(vec![(stats::VITALITY, -2)])).build();
+        inventory::add_item(&w, p, ring);
+        let pet = w.spawn().with(Name::new("pet")).build();
+        w.set_parent(pet, p);
+        schedule.run(&mut w);
+        let mut frames = SnapshotRing::new(8);
+        frames.push(0, snapshot(&w).unwrap());
+
+        //a frame that's about to not have happened
+        w.update(p, |pos: &mut Position| pos.x = 50.0);
+        w.delete_entity(pet);
+        let amulet = w.spawn().with(Name::new("amulet")).with(ActiveEffect::new(vec![(stats::VITALITY, 5)])).build();
+        inventory::add_item(&w, p, amulet);
+        w.update_entities();
+        assert!(!w.is_alive(pet));
+
+        restore(&mut w, frames.get(0).unwrap()).unwrap();
+        assert_eq!(w.get_value::<Position>(p).x, 1.0);
+        assert!(w.is_alive(pet));
+        assert!(!w.is_alive(amulet));
+        assert_eq!(w.children(p), vec![ring, pet]);
+        assert_eq!(w.get_value::<Inventory>(p).items, vec![ring]);
+        //the amulet's buff was still waiting to be applied, and mustn't be
+        schedule.run(&mut w);
+        assert_eq!(stats::get_max(&w, p, stats::VITALITY), 30);
+        //hooks work as normal from here on
+        w.delete_entity(p);
+        w.update_entities();
+        assert!(w.entities().is_empty());
+    }
+
+    #[test]
+    fn same_world_same_bytes() {
+        let (w, _) = prepare_world();
+        for (i, name) in ["kay", "slime", "potion"].iter().enumerate() {
+            let e = w.spawn().with(Name::new(*name)).with(Inventory::new()).build();
+            stats::set_base(&w, e, stats::VITALITY, i as i32);
+            stats::set_base(&w, e, stats::STRENGTH, 2);
+            stats::buff(&w, e, stats::STRENGTH, "ring", 1);
+        }
+        let snap = snapshot(&w).unwrap();
+        assert_eq!(snap.as_bytes(), snapshot(&w).unwrap().as_bytes());
+        //and the same again after a round trip, which builds all new maps
+        let (mut other, _) = prepare_world();
+        restore(&mut other, &snap).unwrap();
+        assert_eq!(snap.as_bytes(), snapshot(&other).unwrap().as_bytes());
+    }
+
+    #[test]
+    fn clock_rolls_back_too() {
+        let (mut w, mut schedule) = prepare_world();
+        let e = w.spawn().with_bundle(PhysicsBundle{
+            position: Position{x: 0.0, y: 0.0},
+            velocity: Velocity{x: 8.0, y: 0.0},
+            friction: Friction{x: 1.0, y: 1.0},
+        }).build();
+        let mut timestep = FixedTimestep::new(4.0);
+        //a step and a bit, then the same again from the snapshot, which has to come out exactly the same
+        timestep.advance(&mut w, &mut schedule, Duration::from_millis(300));
+        let snap = snapshot(&w).unwrap();
+        timestep.advance(&mut w, &mut schedule, Duration::from_millis(200));
+        let (x, steps) = (w.get_value::<Position>(e).x, w.resource::<Time>().steps);
+        assert_eq!(steps, 2);
+        restore(&mut w, &snap).unwrap();
+        assert_eq!((w.resource::<Time>().steps, w.resource::<Time>().behind), (1, Duration::from_millis(50)));
+        timestep.advance(&mut w, &mut schedule, Duration::from_millis(200));
+        assert_eq!((w.get_value::<Position>(e).x, w.resource::<Time>().steps), (x, steps));
+    }
+
+    #[derive(Clone)]
+    struct Sprite;
+    impl Component for Sprite {
+        type Storage = VecStorage<Self>;
+    }
+
+    #[test]
+    fn unregistered_components() {
+        let (mut w, _) = prepare_world();
+        w.register_component::<Sprite>();
+        let e = w.spawn().with(Name::new("kay")).with(Sprite).build();
+        //it'd come back without its Sprite, so it doesn't get a snapshot at all
+        assert!(matches!(snapshot(&w), Err(SnapshotError::UnregisteredComponent(c)) if c.ends_with("Sprite")));
+        w.delete::<Sprite>(e);
+        assert!(snapshot(&w).is_ok());
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let (w, _) = prepare_world();
+        let mut frames = SnapshotRing::new(3);
+        for frame in 0..5 {
+            frames.push(frame, snapshot(&w).unwrap());
+        }
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames.oldest(), Some(2));
+        assert!(frames.get(1).is_none());
+        //resimulating from frame 3 replaces 3 and 4
+        frames.push(3, snapshot(&w).unwrap());
+        assert_eq!(frames.latest().map(|(f, _)| f), Some(3));
+        assert!(frames.get(4).is_none());
+    }
+
+    #[test]
+    fn bad_snapshots() {
+        let (mut w, _) = prepare_world();
+        let old = Snapshot::from_bytes(bincode::serialize(&(SNAPSHOT_VERSION + 1)).unwrap());
+        assert!(matches!(restore(&mut w, &old), Err(SnapshotError::WrongVersion(_))));
+        assert!(matches!(restore(&mut w, &Snapshot::from_bytes(vec![1])), Err(SnapshotError::Bincode(_))));
+    }
+}
@@ -0,0 +1,213 @@
+//which entities are where, by Position: a grid of square cells, each listing what's in it
+//update_spatial_hash keeps it up to date once a frame (at the end of PostUpdate, once everything's moved) using change detection,
+//so it's only as current as that: something that moved earlier this frame still shows up where it was last frame
+//i.e. for picking things up: spatial::query_radius(w, &pos, 16.0)
+
+use std::collections::HashMap;
+use world::{GameState, Entity, Resource, Schedule, Stage, Changed};
+use crate::common::Position;
+
+pub struct SpatialHash {
+    cell_size: f64,
+    cells: HashMap<(i64, i64), Vec<Entity>>,
+    positions: HashMap<Entity, (f64, f64)>, //as of the last update, so moving/removing something knows which cell it was in
+}
+impl Resource for SpatialHash {}
+
+impl SpatialHash {
+    pub fn new(cell_size: f64) -> SpatialHash {
+        SpatialHash{cell_size, cells: HashMap::new(), positions: HashMap::new()}
+    }
+    fn cell(&self, x: f64, y: f64) -> (i64, i64) {
+        ((x / self.cell_size).floor() as i64, (y / self.cell_size).floor() as i64)
+    }
+    pub fn insert(&mut self, e: Entity, x: f64, y: f64) {
+        self.remove(e);
+        let cell = self.cell(x, y);
+        self.cells.entry(cell).or_default().push(e);
+        self.positions.insert(e, (x, y));
+    }
+    pub fn remove(&mut self, e: Entity) {
+        if let Some((x, y)) = self.positions.remove(&e) {
+            let cell = self.cell(x, y);
+            let entities = self.cells.get_mut(&cell).unwrap();
+            entities.retain(|&other| other != e);
+            if entities.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    //everything with x0 <= x <= x1 and y0 <= y <= y1
+    pub fn query_rect(&self, x0: f64, y0: f64, x1: f64, y1: f64) -> Vec<Entity> {
+        let ((cx0, cy0), (cx1, cy1)) = (self.cell(x0, y0), self.cell(x1, y1));
+        let inside = |e: &&Entity| {
+            let (x, y) = self.positions[e];
+            x >= x0 && x <= x1 && y >= y0 && y <= y1
+        };
+        //cells go right up to i64::MIN/MAX for huge (or infinite) bounds, so this can't just subtract
+        let span = |a: i64, b: i64| b.checked_sub(a).and_then(|n| n.checked_add(1)).map(|n| n.max(0));
+        let cells_covered = match (span(cx0, cx1), span(cy0, cy1)) {
+            (Some(w), Some(h)) => w.saturating_mul(h),
+            _ => i64::MAX,
+        };
+        if cells_covered == 0 {
+            return Vec::new();
+        }
+        //for big rects it's quicker to go through the cells that actually have something in them
+        if cells_covered > self.cells.len() as i64 {
+            return self.cells.iter()
+                .filter(|(&(cx, cy), _)| cx >= cx0 && cx <= cx1 && cy >= cy0 && cy <= cy1)
+                .flat_map(|(_, entities)| entities.iter().filter(inside))
+                .cloned()
+                .collect();
+        }
+        let mut found = Vec::new();
+        for cx in cx0..=cx1 {
+            for cy in cy0..=cy1 {
+                if let Some(entities) = self.cells.get(&(cx, cy)) {
+                    found.extend(entities.iter().filter(inside));
+                }
+            }
+        }
+        found
+    }
+    pub fn query_radius(&self, x: f64, y: f64, radius: f64) -> Vec<Entity> {
+        let mut found = self.query_rect(x - radius, y - radius, x + radius, y + radius);
+        found.retain(|e| {
+            let (ex, ey) = self.positions[e];
+            (ex - x).powi(2) + (ey - y).powi(2) <= radius * radius
+        });
+        found
+    }
+    //closest thing that passes filter, i.e. |e| e != me
+    //goes out one ring of cells at a time, and stops once nothing farther out could be any closer
+    pub fn nearest(&self, x: f64, y: f64, filter: impl Fn(Entity) -> bool) -> Option<Entity> {
+        let (cx, cy) = self.cell(x, y);
+        let last_ring = self.cells.keys().map(|&(ox, oy)| (ox - cx).abs().max((oy - cy).abs())).max()?;
+        let mut best: Option<(f64, Entity)> = None;
+        for ring in 0..=last_ring {
+            //anything in this ring is at least ring - 1 whole cells away
+            let closest_possible = ((ring - 1).max(0) as f64 * self.cell_size).powi(2);
+            if best.is_some_and(|(d, _)| d <= closest_possible) {
+                break;
+            }
+            for cell in ring_cells(cx, cy, ring) {
+                for &e in self.cells.get(&cell).into_iter().flatten() {
+                    let (ex, ey) = self.positions[&e];
+                    let d = (ex - x).powi(2) + (ey - y).powi(2);
+                    if best.is_none_or(|(b, _)| d < b) && filter(e) {
+                        best = Some((d, e));
+                    }
+                }
+            }
+        }
+        best.map(|(_, e)| e)
+    }
+}
+
+//the cells exactly ring cells away (in both directions, counting diagonals) from (cx, cy)
+fn ring_cells(cx: i64, cy: i64, ring: i64) -> Vec<(i64, i64)> {
+    if ring == 0 {
+        return vec![(cx, cy)];
+    }
+    let mut cells = Vec::with_capacity(8 * ring as usize);
+    for dx in -ring..=ring {
+        cells.push((cx + dx, cy - ring));
+        cells.push((cx + dx, cy + ring));
+    }
+    for dy in -ring + 1..ring {
+        cells.push((cx - ring, cy + dy));
+        cells.push((cx + ring, cy + dy));
+    }
+    cells
+}
+
+//cells are 32 units across unless a SpatialHash of some other size was set before this
+pub fn init(w: &mut GameState, schedule: &mut Schedule) {
+    if !w.has_resource::<SpatialHash>() {
+        w.set_resource(SpatialHash::new(32.0));
+    }
+    schedule.add_system("update_spatial_hash", Stage::PostUpdate, update_spatial_hash)
+        .access::<(Changed<Position>, &Position)>()
+        .writes::<SpatialHash>()
+        .after("propagate_positions");
+}
+
+pub fn update_spatial_hash(w: &GameState) {
+    let mut hash = w.resource_mut::<SpatialHash>();
+    for e in w.removed::<Position>() {
+        hash.remove(e);
+    }
+    w.query::<(Changed<Position>, &Position)>().for_each_entity(|e, (_, pos)| hash.insert(e, pos.x, pos.y));
+}
+
+//the same, but skipping anything that's been deleted since the last update
+pub fn query_rect(w: &GameState, min: &Position, max: &Position) -> Vec<Entity> {
+    let mut found = w.resource::<SpatialHash>().query_rect(min.x, min.y, max.x, max.y);
+    found.retain(|&e| w.is_alive(e));
+    found
+}
+pub fn query_radius(w: &GameState, at: &Position, radius: f64) -> Vec<Entity> {
+    let mut found = w.resource::<SpatialHash>().query_radius(at.x, at.y, radius);
+    found.retain(|&e| w.is_alive(e));
+    found
+}
+pub fn nearest(w: &GameState, at: &Position, filter: impl Fn(Entity) -> bool) -> Option<Entity> {
+    w.resource::<SpatialHash>().nearest(at.x, at.y, |e| w.is_alive(e) && filter(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common;
+
+    fn prepare_world() -> (GameState, Schedule<'static>) {
+        let mut w = GameState::new();
+        let mut schedule = Schedule::new();
+        common::init(&mut w, &mut schedule);
+        init(&mut w, &mut schedule);
+        (w, schedule)
+    }
+
+    #[test]
+    fn neighbourhood() {
+        let (mut w, mut schedule) = prepare_world();
+        let p = w.spawn().with(Position{x: 0.0, y: 0.0}).build();
+        let near = w.spawn().with(Position{x: 10.0, y: -5.0}).build();
+        let far = w.spawn().with(Position{x: 200.0, y: 150.0}).build();
+        let nowhere = w.create_entity();
+        schedule.run(&mut w);
+        let at = Position{x: 0.0, y: 0.0};
+        let mut found = query_radius(&w, &at, 20.0);
+        found.sort();
+        assert_eq!(found, vec![p, near]);
+        assert_eq!(query_rect(&w, &Position{x: 5.0, y: -10.0}, &Position{x: 500.0, y: 500.0}).len(), 2);
+        let everywhere = (Position{x: f64::NEG_INFINITY, y: f64::NEG_INFINITY}, Position{x: f64::INFINITY, y: f64::INFINITY});
+        assert_eq!(query_rect(&w, &everywhere.0, &everywhere.1).len(), 3);
+        assert!(query_rect(&w, &everywhere.1, &everywhere.0).is_empty());
+        assert_eq!(query_radius(&w, &at, f64::INFINITY).len(), 3);
+        assert_eq!(nearest(&w, &at, |e| e != p), Some(near));
+        assert_eq!(nearest(&w, &at, |e| e != p && e != near), Some(far));
+
+        //moved
+        w.update(far, |pos: &mut Position| *pos = Position{x: 3.0, y: 3.0});
+        schedule.run(&mut w);
+        assert_eq!(nearest(&w, &at, |e| e != p), Some(far));
+        //gained one, lost one, deleted
+        w.insert(nowhere, Position{x: -1.0, y: 0.0});
+        w.delete::<Position>(near);
+        w.delete_entity(far);
+        //far's gone straight away, since it's not alive; the rest waits for the next update
+        assert_eq!(nearest(&w, &at, |e| e != p), Some(near));
+        schedule.run(&mut w);
+        assert_eq!(nearest(&w, &at, |e| e != p), Some(nowhere));
+        assert_eq!(w.resource::<SpatialHash>().len(), 2);
+    }
+}
@@ -1,9 +1,12 @@
-use std::collections::HashMap;
-use world::{GameState, Entity, Component};
+use std::collections::{HashMap, BTreeMap};
+use world::{GameState, Entity, Component, Schedule, Stage, Events, EventCursor};
 use world::storage::VecStorage;
+use crate::events::BuffEvent;
+use crate::prefab;
+use serde_derive::{Serialize, Deserialize};
 
 pub use self::Stat::*; //just so nobody has to type 'Stat' again for the enum
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum Stat {
     VITALITY,       //max hp
@@ -15,14 +18,15 @@ pub enum Stat {
 }
 
 //not really a stat, but related
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Health(pub i32); //current hp; max determined by Vitality
 impl Component for Health {
     type Storage = VecStorage<Self>;
 }
 
-#[derive(Clone)]
-struct Stats(HashMap<Stat, HashMap<Option<&'static str>, i32>>);
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "SavedStats", into = "SavedStats")]
+struct Stats(HashMap<Stat, HashMap<Option<String>, i32>>);
 impl Component for Stats {
     type Storage = VecStorage<Self>;
 }
@@ -31,32 +35,95 @@ impl Stats {
         Stats(HashMap::new())
     }
 }
+//how Stats go in a save: json keys can't be null, so the base value's under ""
+//sorted, so the same Stats always come out the same (i.e. for comparing snapshots)
+type SavedStats = BTreeMap<Stat, BTreeMap<String, i32>>;
+impl From<SavedStats> for Stats {
+    fn from(saved: SavedStats) -> Stats {
+        Stats(saved.into_iter().map(|(stat, values)| {
+            (stat, values.into_iter().map(|(source, v)| {
+                (if source.is_empty() { None } else { Some(source) }, v)
+            }).collect())
+        }).collect())
+    }
+}
+impl From<Stats> for SavedStats {
+    fn from(Stats(stats): Stats) -> SavedStats {
+        stats.into_iter().map(|(stat, values)| {
+            (stat, values.into_iter().map(|(source, v)| (source.unwrap_or_default(), v)).collect())
+        }).collect()
+    }
+}
 
-pub fn init(w: &mut GameState) {
-    w.register_component::<Stats>();
-    w.register_component::<Health>();
+pub fn init(w: &mut GameState, schedule: &mut Schedule) {
+    //prefabs only get to set base stats, i.e. "Stats": {"VITALITY": 12}
+    prefab::register_with(w, "Stats", |v| {
+        let base: HashMap<Stat, i32> = serde_json::from_value(v)?;
+        Ok(Stats(base.into_iter().map(|(stat, value)| (stat, vec![(None, value)].into_iter().collect())).collect()))
+    });
+    prefab::register::<Health>(w, "Health");
+    w.on_insert::<Stats>(health_tracks_vitality);
+    w.on_remove::<Stats>(|w, i, _| w.delete::<Health>(i));
+    w.add_event::<BuffEvent>(); //already there if events::init ran first, but apply_buffs can't do without it
+    let mut buffs = EventCursor::new();
+    schedule.add_system("apply_buffs", Stage::PostUpdate, move |w| apply_buffs(w, &mut buffs))
+        .access::<(&mut Stats, &mut Health)>()
+        .reads::<Events<BuffEvent>>()
+        .before("clamp_health");
+    schedule.add_system("clamp_health", Stage::PostUpdate, clamp_health).access::<(&mut Health, &Stats)>();
 }
 
+//whatever inventory (or anyone else) asked for since last time
+pub fn apply_buffs(w: &GameState, cursor: &mut EventCursor<BuffEvent>) {
+    for event in w.read_events(cursor) {
+        match event {
+            BuffEvent::Added{entity, stat, source, amount} => buff(w, entity, stat, &source, amount),
+            BuffEvent::Removed{entity, source} => unbuff(w, entity, &source),
+        }
+    }
+}
 
-pub fn set_base(w: &GameState, i: Entity, stat: Stat, value: i32) {
-    match w.clone::<Stats>(i) {
-        Some(Stats(ref mut stats)) => {
-            let s = stats.entry(stat).or_insert(HashMap::new());
-            s.insert(None, value);
-        },
-        None => {
-            w.insert(i, Stats::new());
-            w.update(i, |Stats(ref mut stats)| {
-                let s = stats.entry(stat).or_insert(HashMap::new());
-                s.insert(None, value);
-            });
+//healing past max or losing a Vitality buff can leave hp above max, so pull it back down once a frame
+pub fn clamp_health(w: &GameState) {
+    w.query::<(&mut Health, &Stats)>().for_each(|(Health(hp), Stats(stats))| {
+        if let Some(max) = stats.get(&VITALITY).map(|s| s.values().sum::<i32>()) {
+            if *hp > max {
+                *hp = max;
+            }
+        }
+    });
+}
+
+
+//special cases for associated values
+//Health shows up (full) along with Vitality, and never gets left above max when Vitality goes down
+//Stats are private to this file: whole ones coming in (i.e. from prefabs) go through the hook,
+//and set_base/buff/unbuff change them in place and call sync_health themselves
+fn health_tracks_vitality(w: &GameState, i: Entity, _: &Stats) {
+    sync_health(w, i);
+}
+fn sync_health(w: &GameState, i: Entity) {
+    let mut max = None;
+    w.read(i, |Stats(stats)| max = stats.get(&VITALITY).map(|s| s.values().sum::<i32>()));
+    if let Some(max) = max {
+        match w.clone::<Health>(i) {
+            Some(Health(hp)) if hp <= max => (),
+            _ => w.insert(i, Health(max)),
         }
     }
+}
 
-    //special cases for associated values
-    match stat {
-        VITALITY => w.insert(i, Health(value)),
-        _ => {}
+//setting base Vitality fills Health back up to it
+pub fn set_base(w: &GameState, i: Entity, stat: Stat, value: i32) {
+    if !w.has_component::<Stats>(i) {
+        w.insert(i, Stats::new());
+    }
+    w.update(i, |Stats(stats)| {
+        stats.entry(stat).or_default().insert(None, value);
+    });
+    if stat == VITALITY {
+        w.insert(i, Health(value));
+        sync_health(w, i); //in case it's debuffed below that
     }
 }
 
@@ -70,11 +137,11 @@ pub fn get_base(w: &GameState, i: Entity, stat: Stat) -> i32 {
     if let Some(Stats(stats)) = w.clone(i) {
         if let Some(s) = stats.get(&stat) {
             if let Some(s) = s.get(&None) {
-                return s.clone();
+                return *s;
             }
         }
     }
-    return 0;
+    0
 }
 //gets sum of base stat + all buffs/debuffs
 pub fn get_max(w: &GameState, i: Entity, stat: Stat) -> i32 {
@@ -141,30 +208,22 @@ pub fn modify(w: &GameState, i: Entity, stat: Stat, amount: i32) {
 }
 
 //note: these functions won't work if the entity doesn't have a base stat
-pub fn buff(w: &GameState, i: Entity, stat: Stat, name: &'static str, buff: i32) {
-    w.update(i, |Stats(ref mut stats)| {
-        let s = stats.entry(stat).or_insert(HashMap::new());
-        s.insert(Some(name), buff);
+pub fn buff(w: &GameState, i: Entity, stat: Stat, name: &str, buff: i32) {
+    w.update(i, |Stats(stats)| {
+        stats.entry(stat).or_default().insert(Some(name.to_string()), buff);
     });
-
-    //special cases for associated values
-    match stat {
-        VITALITY => {
-            //in the case that Vitality is buffed down to lower than current HP,
-            //then subtract from HP as well
-            if get_max(w, i, stat) < get(w, i, stat) {
-                set(w, i, stat, get_max(w, i, stat));
-            }
-        }
-        _ => {}
+    if stat == VITALITY {
+        sync_health(w, i);
     }
 }
-pub fn unbuff(w: &GameState, i: Entity, name: &'static str) {
-    w.update(i, |Stats(ref mut stats)| {
+pub fn unbuff(w: &GameState, i: Entity, name: &str) {
+    let source = Some(name.to_string());
+    w.update(i, |Stats(stats)| {
         for s in stats.values_mut() {
-            s.remove(&Some(name));
+            s.remove(&source);
         }
     });
+    sync_health(w, i);
 }
 
 
@@ -175,13 +234,13 @@ mod tests {
 
     fn prepare_world() -> GameState {
         let mut w = GameState::new();
-        init(&mut w);
+        init(&mut w, &mut Schedule::new());
         w
     }
 
     #[test]
     fn base_stats() {
-        let mut w = prepare_world();
+        let w = prepare_world();
         let stats_test_entity = w.create_entity();
         set_base(&w, stats_test_entity, VITALITY, 32);
         assert_eq!(get_base(&w, stats_test_entity, VITALITY), 32); 
@@ -189,7 +248,7 @@ mod tests {
     }
     #[test]
     fn modify_value() {
-        let mut w = prepare_world();
+        let w = prepare_world();
         let stats_test_entity = w.create_entity();
         set_base(&w, stats_test_entity, VITALITY, 32);
         assert_eq!(get_base(&w, stats_test_entity, VITALITY), 32); 
@@ -201,7 +260,7 @@ mod tests {
     }
     #[test]
     fn buff_base() {
-        let mut w = prepare_world();
+        let w = prepare_world();
         let stats_test_entity = w.create_entity();
         set_base(&w, stats_test_entity, VITALITY, 32);
         buff(&w, stats_test_entity, VITALITY, "health buff 1", -5);
@@ -212,7 +271,7 @@ mod tests {
     }
     #[test]
     fn buff_vitality() {
-        let mut w = prepare_world();
+        let w = prepare_world();
         let stats_test_entity = w.create_entity();
         set_base(&w, stats_test_entity, VITALITY, 32);
         buff(&w, stats_test_entity, VITALITY, "health buff 1", -5);
@@ -221,4 +280,41 @@ mod tests {
         assert_eq!(get(&w, stats_test_entity, VITALITY), 27);
         w.delete_entity(stats_test_entity);
     }
+    #[test]
+    fn health_clamped() {
+        let mut w = GameState::new();
+        let mut schedule = Schedule::new();
+        init(&mut w, &mut schedule);
+        let stats_test_entity = w.create_entity();
+        set_base(&w, stats_test_entity, VITALITY, 32);
+        modify(&w, stats_test_entity, VITALITY, 10);
+        assert_eq!(get(&w, stats_test_entity, VITALITY), 42);
+        schedule.run(&mut w);
+        assert_eq!(get(&w, stats_test_entity, VITALITY), 32);
+    }
+    #[test]
+    fn health_follows_stats() {
+        let mut w = prepare_world();
+        let stats_test_entity = w.create_entity();
+        set_base(&w, stats_test_entity, STRENGTH, 5);
+        assert!(!w.has_component::<Health>(stats_test_entity));
+        set_base(&w, stats_test_entity, VITALITY, 32);
+        assert_eq!(get(&w, stats_test_entity, VITALITY), 32);
+        //raising max doesn't heal
+        modify(&w, stats_test_entity, VITALITY, -10);
+        buff(&w, stats_test_entity, VITALITY, "health buff 1", 5);
+        assert_eq!(get(&w, stats_test_entity, VITALITY), 22);
+        //setting the base does, up to the new base
+        set_base(&w, stats_test_entity, VITALITY, 30);
+        assert_eq!(get(&w, stats_test_entity, VITALITY), 30);
+        //and swapping the buff for a debuff pulls it down with max
+        buff(&w, stats_test_entity, VITALITY, "health buff 1", -5);
+        assert_eq!(get(&w, stats_test_entity, VITALITY), 25);
+        w.delete::<Stats>(stats_test_entity);
+        assert!(!w.has_component::<Health>(stats_test_entity));
+        set_base(&w, stats_test_entity, VITALITY, 32);
+        w.delete_entity(stats_test_entity);
+        w.update_entities();
+        assert_eq!(w.clone::<Health>(stats_test_entity).map(|Health(hp)| hp), None);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,149 @@
+//how long everything's been going, and a fixed-step loop to go with it
+//the simulation (PreUpdate, Update, PostUpdate) always moves on by exactly Time::step seconds at a time, however fast frames
+//actually come in, so the game runs at the same speed whatever the monitor's refresh rate is
+//Render runs once a frame, somewhere between two steps; common::interpolate draws things part of the way between them
+//i.e. let mut timestep = FixedTimestep::new(60.0); ... loop { timestep.run(&mut w, &mut schedule); }
+
+use std::time::{Duration, Instant};
+use world::{GameState, Resource, Schedule, Stage};
+use serde_derive::{Serialize, Deserialize};
+use crate::snapshot;
+
+//part of every snapshot, so rolling back rolls the clock back too
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Time {
+    pub delta: f64, //seconds since the last frame
+    pub elapsed: f64, //seconds since the first one
+    pub frame: u64, //frames so far
+    pub step: f64, //seconds per simulation step, i.e. what velocities get multiplied by
+    pub steps: u64, //simulation steps so far
+    pub alpha: f64, //how far this frame is from the last step (0) to the next one (1)
+    pub behind: Duration, //time that's passed but hasn't been stepped through yet; here rather than in FixedTimestep so it gets snapshotted
+}
+impl Resource for Time {}
+
+impl Time {
+    pub fn new(step: f64) -> Time {
+        //without a FixedTimestep every frame is exactly one step, so things get drawn right where they are
+        Time{delta: 0.0, elapsed: 0.0, frame: 0, step, steps: 0, alpha: 1.0, behind: Duration::ZERO}
+    }
+}
+
+//60 steps a second unless a Time with some other step was set before this
+pub fn init(w: &mut GameState) {
+    if !w.has_resource::<Time>() {
+        w.set_resource(Time::new(1.0 / 60.0));
+    }
+    snapshot::register_resource::<Time>(w, "Time");
+}
+
+const SIMULATION: [Stage; 3] = [Stage::PreUpdate, Stage::Update, Stage::PostUpdate];
+
+pub struct FixedTimestep {
+    step: Duration,
+    max_steps: u32, //per frame; any more than that gets dropped, so a slow frame doesn't make the next one even slower
+    last_frame: Option<Instant>,
+}
+
+impl FixedTimestep {
+    //rate is in steps per second
+    pub fn new(rate: f64) -> FixedTimestep {
+        FixedTimestep{step: Duration::from_secs_f64(1.0 / rate), max_steps: 8, last_frame: None}
+    }
+    pub fn with_max_steps(self, max_steps: u32) -> FixedTimestep {
+        FixedTimestep{max_steps, ..self}
+    }
+
+    //one frame: however many steps are due since the last one, then Render
+    pub fn run(&mut self, w: &mut GameState, schedule: &mut Schedule) {
+        let now = Instant::now();
+        //the very first frame gets a step, so there's something to draw
+        let delta = self.last_frame.map_or(self.step, |last| now - last);
+        self.last_frame = Some(now);
+        self.advance(w, schedule, delta);
+    }
+
+    //same, for a frame that took exactly delta, i.e. for tests or replays
+    pub fn advance(&mut self, w: &mut GameState, schedule: &mut Schedule, delta: Duration) {
+        {
+            let mut time = w.resource_mut::<Time>();
+            time.step = self.step.as_secs_f64();
+            time.delta = delta.as_secs_f64();
+            time.elapsed += time.delta;
+            time.frame += 1;
+            time.behind = (time.behind + delta).min(self.step * self.max_steps);
+        }
+        while w.resource::<Time>().behind >= self.step {
+            w.resource_mut::<Time>().behind -= self.step;
+            schedule.run_stages(w, &SIMULATION);
+            //once per step, so systems reading each other's events don't miss any on frames with more than one
+            w.update_events();
+            w.resource_mut::<Time>().steps += 1;
+        }
+        {
+            let mut time = w.resource_mut::<Time>();
+            time.alpha = time.behind.as_secs_f64() / self.step.as_secs_f64();
+        }
+        schedule.run_stages(w, &[Stage::Render]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{self, Position, Velocity, Friction, PhysicsBundle};
+
+    fn prepare_world() -> (GameState, Schedule<'static>) {
+        let mut w = GameState::new();
+        let mut schedule = Schedule::new();
+        common::init(&mut w, &mut schedule);
+        (w, schedule)
+    }
+
+    #[test]
+    fn fixed_steps() {
+        let (mut w, mut schedule) = prepare_world();
+        let e = w.spawn().with_bundle(PhysicsBundle{
+            position: Position{x: 0.0, y: 0.0},
+            velocity: Velocity{x: 8.0, y: 0.0},
+            friction: Friction{x: 1.0, y: 1.0},
+        }).build();
+        let mut timestep = FixedTimestep::new(4.0).with_max_steps(4);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        //two quarter-second steps, and most of the way to a third
+        timestep.advance(&mut w, &mut schedule, Duration::from_millis(600));
+        assert_eq!(w.resource::<Time>().steps, 2);
+        assert!(close(w.resource::<Time>().alpha, 0.4));
+        assert_eq!(w.get_value::<Position>(e).x, 4.0);
+        //drawn 40% of the way from where it was before the last step
+        assert!(close(common::interpolate(&w, e, &w.clone::<Position>(e).unwrap()).x, 2.8));
+
+        //a frame that's too quick for a step still gets drawn
+        timestep.advance(&mut w, &mut schedule, Duration::from_millis(100));
+        assert_eq!(w.resource::<Time>().steps, 2);
+        assert!(close(w.resource::<Time>().alpha, 0.8));
+        //and a really slow one only catches up so far
+        timestep.advance(&mut w, &mut schedule, Duration::from_secs(10));
+        let time = w.resource::<Time>();
+        assert_eq!((time.steps, time.frame), (6, 3));
+        assert!(close(time.elapsed, 10.7));
+    }
+
+    #[test]
+    fn same_speed_at_any_rate() {
+        //a second's worth of steps, at two different rates
+        for rate in [4.0, 16.0] {
+            let (mut w, mut schedule) = prepare_world();
+            let e = w.spawn().with_bundle(PhysicsBundle{
+                position: Position{x: 0.0, y: 0.0},
+                velocity: Velocity{x: 8.0, y: 0.0},
+                friction: Friction{x: 0.25, y: 1.0},
+            }).build();
+            let mut timestep = FixedTimestep::new(rate).with_max_steps(rate as u32);
+            timestep.advance(&mut w, &mut schedule, Duration::from_secs(1));
+            //loses three quarters of its velocity over the second, however it's cut up
+            assert!((w.get_value::<Velocity>(e).x - 2.0).abs() < 1e-9);
+        }
+    }
+}
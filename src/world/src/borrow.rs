@@ -0,0 +1,112 @@
+use std::any::TypeId;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{RwLockReadGuard, RwLockWriteGuard};
+#[cfg(debug_assertions)]
+use std::sync::Mutex;
+#[cfg(debug_assertions)]
+use std::panic::Location;
+#[cfg(debug_assertions)]
+use std::thread::{self, ThreadId};
+use crate::error::Error;
+
+//locked storages (or resources), as handed out by GameState and held by queries
+//same as the RwLock guards, plus they let go of their entry in the BorrowTracker when dropped
+pub struct StorageRef<'w, S> {
+    guard: RwLockReadGuard<'w, S>,
+    _borrow: Borrow<'w>,
+}
+pub struct StorageMut<'w, S> {
+    guard: RwLockWriteGuard<'w, S>,
+    _borrow: Borrow<'w>,
+}
+
+impl<'w, S> StorageRef<'w, S> {
+    pub(crate) fn new(guard: RwLockReadGuard<'w, S>, borrow: Borrow<'w>) -> StorageRef<'w, S> {
+        StorageRef{guard, _borrow: borrow}
+    }
+}
+impl<'w, S> StorageMut<'w, S> {
+    pub(crate) fn new(guard: RwLockWriteGuard<'w, S>, borrow: Borrow<'w>) -> StorageMut<'w, S> {
+        StorageMut{guard, _borrow: borrow}
+    }
+}
+
+impl<S> Deref for StorageRef<'_, S> {
+    type Target = S;
+    fn deref(&self) -> &S {
+        &self.guard
+    }
+}
+impl<S> Deref for StorageMut<'_, S> {
+    type Target = S;
+    fn deref(&self) -> &S {
+        &self.guard
+    }
+}
+impl<S> DerefMut for StorageMut<'_, S> {
+    fn deref_mut(&mut self) -> &mut S {
+        &mut self.guard
+    }
+}
+
+//RwLock just hangs if the same thread asks for a write lock it (or a read lock) already has,
+//so debug builds write down who's got what and from where, and complain before it gets that far
+//release builds skip all of this and go straight to the lock
+#[derive(Default)]
+pub(crate) struct BorrowTracker {
+    #[cfg(debug_assertions)]
+    held: Mutex<Vec<Held>>,
+    #[cfg(debug_assertions)]
+    next_token: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(debug_assertions)]
+struct Held {
+    token: usize,
+    type_id: TypeId,
+    thread: ThreadId,
+    mutable: bool,
+    at: &'static Location<'static>,
+}
+
+pub(crate) struct Borrow<'w> {
+    #[cfg(debug_assertions)]
+    tracker: &'w BorrowTracker,
+    #[cfg(debug_assertions)]
+    token: usize,
+    _marker: PhantomData<&'w ()>,
+}
+
+impl BorrowTracker {
+    //call before actually locking; reads only conflict with writes, writes conflict with everything
+    #[track_caller]
+    #[cfg(debug_assertions)]
+    pub(crate) fn acquire(&self, type_id: TypeId, name: &'static str, mutable: bool) -> Result<Borrow<'_>, Error> {
+        use std::sync::atomic::Ordering;
+        let wanted_at = Location::caller();
+        let thread = thread::current().id();
+        let mut held = self.held.lock().unwrap();
+        if let Some(h) = held.iter().find(|h| h.type_id == type_id && h.thread == thread && (mutable || h.mutable)) {
+            return Err(Error::AlreadyBorrowed{component: name, held_at: h.at, wanted_at});
+        }
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        held.push(Held{token, type_id, thread, mutable, at: wanted_at});
+        Ok(Borrow{tracker: self, token, _marker: PhantomData})
+    }
+    #[track_caller]
+    #[cfg(not(debug_assertions))]
+    pub(crate) fn acquire(&self, _type_id: TypeId, _name: &'static str, _mutable: bool) -> Result<Borrow<'_>, Error> {
+        Ok(Borrow{_marker: PhantomData})
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for Borrow<'_> {
+    fn drop(&mut self) {
+        let mut held = self.tracker.held.lock().unwrap();
+        if let Some(i) = held.iter().position(|h| h.token == self.token) {
+            held.swap_remove(i);
+        }
+    }
+}
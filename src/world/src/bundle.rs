@@ -0,0 +1,170 @@
+use crate::world::{GameState, Component, Entity};
+use crate::signature::Signature;
+
+//a new entity (or an existing one, see GameState::build), being put together one component at a time
+//each with() goes straight into its storage, but the signature (and so any archetype moves) and on_insert hooks
+//only happen once, when it's done; i.e. let p = w.spawn().with(Player).with(Position{x: 0.0, y: 0.0}).build();
+//dropping it without calling build() finishes it off all the same
+#[must_use = "call build() to get the entity"]
+pub struct EntityBuilder<'w> {
+    world: &'w GameState,
+    entity: Entity,
+    signature: Signature,
+    tick: u64,
+    inserted: Vec<fn(&GameState, Entity)>, //for firing the hooks at the end
+}
+
+impl<'w> EntityBuilder<'w> {
+    pub(crate) fn new(world: &'w GameState, entity: Entity) -> EntityBuilder<'w> {
+        EntityBuilder{world, entity, signature: Signature::new(), tick: world.this_run(), inserted: Vec::new()}
+    }
+    //stale handles are ignored, same as insert()
+    #[track_caller]
+    pub fn with<C: Component>(mut self, c: C) -> EntityBuilder<'w> {
+        let id = self.world.component_id::<C>();
+        if !self.world.is_current(self.entity) {
+            return self;
+        }
+        //on_replace is for things that were there before the builder was, not for calling with() twice
+        if !self.signature.contains(id) {
+            self.world.fire_on_replace::<C>(self.entity);
+        }
+        self.world.lock_write::<C>().insert(self.entity.id(), c, self.tick);
+        if !self.signature.contains(id) {
+            self.signature.insert(id);
+            self.inserted.push(GameState::fire_on_insert::<C>);
+        }
+        self
+    }
+    pub fn with_bundle<B: Bundle>(self, bundle: B) -> EntityBuilder<'w> {
+        bundle.add_to(self)
+    }
+    //the entity's handle, i.e. for pointing other components at it before it's done
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+    pub fn build(self) -> Entity {
+        self.entity //the rest happens in drop
+    }
+}
+
+impl Drop for EntityBuilder<'_> {
+    fn drop(&mut self) {
+        self.world.spawned(self.entity, std::mem::take(&mut self.signature));
+        for fire in std::mem::take(&mut self.inserted) {
+            fire(self.world, self.entity);
+        }
+    }
+}
+
+//a group of components that go together, i.e. everything physics needs
+//already done for tuples (of components, or of other bundles); for a struct, pass each field along:
+//  fn add_to(self, b: EntityBuilder<'_>) -> EntityBuilder<'_> { b.with(self.position).with(self.velocity) }
+pub trait Bundle {
+    fn add_to(self, builder: EntityBuilder<'_>) -> EntityBuilder<'_>;
+}
+
+impl<C: Component> Bundle for C {
+    fn add_to(self, builder: EntityBuilder<'_>) -> EntityBuilder<'_> {
+        builder.with(self)
+    }
+}
+
+macro_rules! impl_bundle {
+    ($($tp:ident),*) => (
+        impl<$($tp: Bundle),*> Bundle for ($($tp,)*) {
+            #[allow(non_snake_case)]
+            fn add_to(self, builder: EntityBuilder<'_>) -> EntityBuilder<'_> {
+                let ($($tp,)*) = self;
+                $(let builder = $tp.add_to(builder);)*
+                builder
+            }
+        }
+    );
+}
+impl_bundle!(A);
+impl_bundle!(A, B);
+impl_bundle!(A, B, C);
+impl_bundle!(A, B, C, D);
+impl_bundle!(A, B, C, D, E);
+impl_bundle!(A, B, C, D, E, F);
+impl_bundle!(A, B, C, D, E, F, G);
+impl_bundle!(A, B, C, D, E, F, G, H);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Resource;
+    use crate::storage::{VecStorage, ArchetypeStorage};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Position(i32);
+    impl Component for Position {
+        type Storage = ArchetypeStorage<Self>;
+    }
+    #[derive(Clone, Debug, PartialEq)]
+    struct Velocity(i32);
+    impl Component for Velocity {
+        type Storage = ArchetypeStorage<Self>;
+    }
+    #[derive(Clone, Debug, PartialEq)]
+    struct Name(&'static str);
+    impl Component for Name {
+        type Storage = VecStorage<Self>;
+    }
+
+    struct Mover {
+        position: Position,
+        velocity: Velocity,
+    }
+    impl Bundle for Mover {
+        fn add_to(self, builder: EntityBuilder<'_>) -> EntityBuilder<'_> {
+            builder.with(self.position).with(self.velocity)
+        }
+    }
+
+    //how many components the entity had by the time each hook ran
+    struct Seen(Vec<usize>);
+    impl Resource for Seen {}
+    fn named(w: &GameState, e: Entity, _: &Name) {
+        let n = w.components_of(e).len();
+        w.resource_mut::<Seen>().0.push(n);
+    }
+
+    fn prepare_world() -> GameState {
+        let mut w = GameState::new();
+        w.register_component::<Position>();
+        w.register_component::<Velocity>();
+        w.register_component::<Name>();
+        w.set_resource(Seen(Vec::new()));
+        w.on_insert::<Name>(named);
+        w
+    }
+
+    #[test]
+    fn spawn_with_bundles() {
+        let w = prepare_world();
+        let a = w.spawn().with(Name("a")).with_bundle(Mover{position: Position(1), velocity: Velocity(2)}).build();
+        let b = w.spawn().with_bundle((Position(3), Name("b"))).build();
+        let c = w.spawn().with(Position(4)).with(Position(5)).build();
+        assert_eq!(w.components_of(a).len(), 3);
+        assert_eq!(w.clone::<Velocity>(a), Some(Velocity(2)));
+        assert_eq!(w.clone::<Position>(c), Some(Position(5)));
+        //hooks only go off once everything's in
+        assert_eq!(w.resource::<Seen>().0, vec![3, 2]);
+        //and archetype tables are sorted out
+        let mut moving = Vec::new();
+        w.query::<(&Position, &Velocity)>().for_each_entity(|e, _| moving.push(e));
+        assert_eq!(moving, vec![a]);
+        let mut positions = Vec::new();
+        w.query::<(&Position,)>().for_each_entity(|e, _| positions.push(e));
+        positions.sort();
+        assert_eq!(positions, vec![a, b, c]);
+        //things inserted the usual way while it's being built aren't lost
+        let builder = w.spawn();
+        let d = builder.entity();
+        w.insert(d, Name("d"));
+        assert_eq!(builder.with(Position(6)).build(), d);
+        assert_eq!(w.components_of(d).len(), 2);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::world::{GameState, Component, Entity};
+
+pub(crate) type Command = Box<dyn FnOnce(&mut GameState) + Send>;
+
+//structural changes recorded now and applied at the next update_entities()
+//so they're safe to make from inside update()/query() closures without fighting over locks
+//i.e. w.commands().delete::<Position>(item);
+pub struct Commands<'w> {
+    world: &'w GameState,
+}
+
+impl<'w> Commands<'w> {
+    pub(crate) fn new(world: &'w GameState) -> Commands<'w> {
+        Commands{world}
+    }
+    //the handle is good right away, it just won't have any components until the commands run
+    pub fn create_entity(&self) -> Entity {
+        self.world.create_entity()
+    }
+    pub fn insert<C: Component>(&self, entity: Entity, c: C) {
+        self.add(move |w| w.insert(entity, c));
+    }
+    pub fn delete<C: Component>(&self, entity: Entity) {
+        self.add(move |w| w.delete::<C>(entity));
+    }
+    pub fn delete_entity(&self, entity: Entity) {
+        self.add(move |w| w.delete_entity(entity));
+    }
+    //anything else that needs the whole world to itself
+    pub fn add(&self, f: impl FnOnce(&mut GameState) + Send + 'static) {
+        self.world.commands.lock().unwrap().push(Box::new(f));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::world::{GameState, Component};
+    use crate::storage::VecStorage;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Counter(i32);
+    impl Component for Counter {
+        type Storage = VecStorage<Self>;
+    }
+
+    #[test]
+    fn deferred_until_sync() {
+        let mut w = GameState::new();
+        w.register_component::<Counter>();
+        let a = w.create_entity();
+        w.insert(a, Counter(0));
+        //none of this would be allowed directly while Counter is locked
+        w.update_all(|e, c: &mut Counter| {
+            c.0 += 1;
+            let spawned = w.commands().create_entity();
+            w.commands().insert(spawned, Counter(10));
+            w.commands().delete::<Counter>(e);
+        });
+        assert_eq!(w.clone::<Counter>(a), Some(Counter(1)));
+        w.update_entities();
+        assert_eq!(w.clone::<Counter>(a), None);
+        let mut total = 0;
+        w.read_all(|_, c: &Counter| total += c.0);
+        assert_eq!(total, 10);
+        w.commands().delete_entity(a);
+        assert!(w.is_alive(a));
+        w.update_entities();
+        assert!(!w.is_alive(a));
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+use std::panic::Location;
+use crate::world::Entity;
+
+//what the try_ versions of the GameState functions hand back instead of panicking
+//(the plain versions still panic on these, except for stale handles/missing components which they just ignore)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    //register_component::<C>() was never called, holds the type name
+    UnregisteredComponent(&'static str),
+    //the entity's been deleted, or its slot's been reused since this handle was made
+    StaleEntity(Entity),
+    //the entity's fine, it just doesn't have one of these
+    MissingComponent(&'static str),
+    //set_resource::<R>() was never called (or it's been removed since)
+    MissingResource(&'static str),
+    //add_index::<C, K>() was never called, holds (C, K)
+    MissingIndex(&'static str, &'static str),
+    //set_parent would have made this entity its own ancestor
+    ParentCycle(Entity),
+    //a Schedule's before/after names a system that was never added
+    UnknownSystem(&'static str),
+    //two systems in a Schedule with the same name
+    DuplicateSystem(&'static str),
+    //this system's before/after constraints go round in a circle, or point at a system from a later stage
+    UnsatisfiableOrder(&'static str),
+    //this thread is already holding the storage in a way that conflicts (only caught in debug builds)
+    //held_at is where the first borrow came from, wanted_at is the one that would have deadlocked
+    AlreadyBorrowed {
+        component: &'static str,
+        held_at: &'static Location<'static>,
+        wanted_at: &'static Location<'static>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnregisteredComponent(name) => write!(f, "component {} was never registered", name),
+            Error::StaleEntity(entity) => write!(f, "entity {:?} has been deleted", entity),
+            Error::MissingComponent(name) => write!(f, "entity has no {}", name),
+            Error::MissingResource(name) => write!(f, "resource {} hasn't been set", name),
+            Error::MissingIndex(c, k) => write!(f, "{} has no index by {}", c, k),
+            Error::ParentCycle(entity) => write!(f, "entity {:?} can't be its own ancestor", entity),
+            Error::UnknownSystem(name) => write!(f, "no system named {}", name),
+            Error::DuplicateSystem(name) => write!(f, "there's already a system named {}", name),
+            Error::UnsatisfiableOrder(name) => write!(f, "system {} can't run in the order it asks for", name),
+            Error::AlreadyBorrowed{component, held_at, wanted_at} => {
+                write!(f, "{} is already borrowed (at {}), can't borrow it again at {}", component, held_at, wanted_at)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,98 @@
+use std::marker::PhantomData;
+use crate::world::Resource;
+
+//a channel of T's, stored as a resource (see GameState::add_event/send/read_events)
+//double buffered: each update() throws out the older half, so anything sent sticks around
+//for this frame and the next, long enough for every system to see it once wherever it is in the schedule
+pub struct Events<T> {
+    previous: Vec<(usize, T)>,
+    current: Vec<(usize, T)>,
+    next_id: usize,
+}
+
+impl<T> Events<T> {
+    pub fn new() -> Events<T> {
+        Events{previous: Vec::new(), current: Vec::new(), next_id: 0}
+    }
+    pub fn send(&mut self, event: T) {
+        self.current.push((self.next_id, event));
+        self.next_id += 1;
+    }
+    //called once a frame, by Schedule::run
+    pub fn update(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+    //everything still buffered, oldest first, i.e. for tests
+    pub fn iter(&self) -> impl Iterator<Item=&T> {
+        self.previous.iter().chain(self.current.iter()).map(|(_, e)| e)
+    }
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Events<T> {
+        Events::new()
+    }
+}
+
+impl<T: Send + Sync + 'static> Resource for Events<T> {}
+
+//where one reader is up to, so every reader gets every event exactly once
+//keep one per system (i.e. in the closure, or in a script's vars)
+pub struct EventCursor<T> {
+    next: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> EventCursor<T> {
+    pub fn new() -> EventCursor<T> {
+        EventCursor{next: 0, _marker: PhantomData}
+    }
+    //everything sent since the last read (that hasn't been cleared out yet)
+    pub fn read<'a>(&mut self, events: &'a Events<T>) -> impl Iterator<Item=&'a T> {
+        let next = self.next;
+        self.next = events.next_id;
+        events.previous.iter().chain(events.current.iter()).filter(move |&&(id, _)| id >= next).map(|(_, e)| e)
+    }
+}
+
+impl<T> Default for EventCursor<T> {
+    fn default() -> EventCursor<T> {
+        EventCursor::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::GameState;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Died(u32);
+
+    #[test]
+    fn cursors_and_buffering() {
+        let mut w = GameState::new();
+        w.add_event::<Died>();
+        let (mut early, mut late) = (EventCursor::<Died>::new(), EventCursor::<Died>::new());
+        w.send(Died(1));
+        assert_eq!(w.read_events(&mut early), vec![Died(1)]);
+        w.send(Died(2));
+        w.update_events();
+        //still there a frame later
+        assert_eq!(w.read_events(&mut early), vec![Died(2)]);
+        assert_eq!(w.read_events(&mut late), vec![Died(1), Died(2)]);
+        assert_eq!(w.read_events(&mut late), vec![]);
+        w.send(Died(3));
+        w.update_events();
+        w.update_events();
+        //but not two
+        assert!(w.resource::<Events<Died>>().is_empty());
+        assert_eq!(w.read_events::<Died>(&mut early), vec![]);
+    }
+}
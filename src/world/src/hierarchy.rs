@@ -0,0 +1,156 @@
+use std::ops::Deref;
+use crate::world::{GameState, Component, Entity};
+use crate::storage::SparseSetStorage;
+use crate::error::Error;
+
+//who this entity belongs to; insert it (or use GameState::set_parent) and the parent's Children keep up by themselves
+//one that would make the entity its own ancestor gets taken straight back off again and panics,
+//however it went on; try_set_parent checks first, for an Err instead
+//children get deleted along with their parent, see GameState::delete_entity
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Parent(pub Entity);
+impl Component for Parent {
+    type Storage = SparseSetStorage<Self>;
+}
+
+//the other side of Parent, oldest first; read-only, since the hooks below are the only thing that should touch it
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Children(pub(crate) Vec<Entity>);
+impl Component for Children {
+    type Storage = SparseSetStorage<Self>;
+}
+impl Deref for Children {
+    type Target = [Entity];
+    fn deref(&self) -> &[Entity] {
+        &self.0
+    }
+}
+
+pub(crate) fn init(w: &mut GameState) {
+    w.register_component::<Parent>();
+    w.register_component::<Children>();
+    w.on_insert::<Parent>(adopted);
+    w.on_replace::<Parent>(disowned);
+    w.on_remove::<Parent>(disowned);
+}
+
+fn adopted(w: &GameState, child: Entity, &Parent(parent): &Parent) {
+    if w.is_ancestor(child, parent) {
+        w.delete::<Parent>(child);
+        panic!("{}", Error::ParentCycle(child));
+    }
+    if w.is_deleted(parent) {
+        //too late to join, it's going to be swept without us otherwise
+        w.delete_entity(child);
+    } else if w.has_component::<Children>(parent) {
+        w.update(parent, |Children(children)| children.push(child));
+    } else {
+        w.insert(parent, Children(vec![child]));
+    }
+}
+fn disowned(w: &GameState, child: Entity, &Parent(parent): &Parent) {
+    w.update(parent, |Children(children)| children.retain(|&c| c != child));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use crate::storage::VecStorage;
+
+    #[derive(Clone)]
+    struct Hat;
+    impl Component for Hat {
+        type Storage = VecStorage<Self>;
+    }
+
+    fn prepare_world() -> GameState {
+        let mut w = GameState::new();
+        w.register_component::<Hat>();
+        w
+    }
+
+    #[test]
+    fn children_follow_parents() {
+        let w = prepare_world();
+        let (a, b, c) = (w.create_entity(), w.create_entity(), w.create_entity());
+        w.set_parent(b, a);
+        w.insert(c, Parent(a));
+        assert_eq!(w.children(a), vec![b, c]);
+        assert_eq!(w.parent(b), Some(a));
+        //moving c over takes it out of a's
+        w.set_parent(c, b);
+        assert_eq!(w.children(a), vec![b]);
+        assert_eq!(w.children(b), vec![c]);
+        w.remove_parent(c);
+        assert_eq!(w.children(b), vec![]);
+        assert_eq!(w.parent(c), None);
+    }
+
+    #[test]
+    fn recursive_delete() {
+        let mut w = prepare_world();
+        let (a, b, c, d) = (w.create_entity(), w.create_entity(), w.create_entity(), w.create_entity());
+        w.set_parent(b, a);
+        w.set_parent(c, b);
+        w.set_parent(d, c);
+        w.insert(c, Hat);
+        //deleting the middle takes everything under it, but not above
+        w.delete_entity(b);
+        assert!(w.is_deleted(c) && w.is_deleted(d));
+        w.update_entities();
+        assert!(w.is_alive(a));
+        assert_eq!(w.children(a), vec![]);
+        assert!([b, c, d].iter().all(|&e| w.is_deleted(e)));
+        //slots got cleaned out properly
+        let e = w.create_entity();
+        assert!(w.clone::<Hat>(e).is_none());
+        assert_eq!(w.parent(e), None);
+
+        //joining a parent that's about to go
+        let f = w.create_entity();
+        w.delete_entity(a);
+        w.set_parent(f, a);
+        w.update_entities();
+        assert!(w.is_deleted(f));
+    }
+
+    #[test]
+    fn no_cycles() {
+        let w = prepare_world();
+        let (a, b, c) = (w.create_entity(), w.create_entity(), w.create_entity());
+        w.set_parent(b, a);
+        w.set_parent(c, b);
+        assert_eq!(w.try_set_parent(a, c), Err(Error::ParentCycle(a)));
+        assert_eq!(w.try_set_parent(a, a), Err(Error::ParentCycle(a)));
+        //turned away before anything changed
+        assert_eq!(w.parent(a), None);
+        assert_eq!(w.children(c), vec![]);
+
+        //inserting it directly gets the same treatment, just as a panic
+        let direct = panic::catch_unwind(AssertUnwindSafe(|| w.insert(a, Parent(c))));
+        assert!(direct.is_err());
+        assert_eq!(w.parent(a), None);
+        assert_eq!(w.children(c), vec![]);
+        let built = panic::catch_unwind(AssertUnwindSafe(|| w.build(a).with(Parent(b)).build()));
+        assert!(built.is_err());
+        assert_eq!(w.parent(a), None);
+    }
+
+    #[test]
+    fn deleting_cycles() {
+        let mut w = prepare_world();
+        let (a, b) = (w.create_entity(), w.create_entity());
+        //the hooks are the only thing that'd stop this, i.e. a save file that's been messed with
+        w.insert_without_hooks(a, Parent(b));
+        w.insert_without_hooks(b, Parent(a));
+        w.insert_without_hooks(a, Children(vec![b]));
+        w.insert_without_hooks(b, Children(vec![a]));
+        assert!(w.is_ancestor(a, b) && w.is_ancestor(b, a));
+        let c = w.create_entity();
+        assert!(!w.is_ancestor(c, a));
+        w.delete_entity(a);
+        w.update_entities();
+        assert!(w.is_deleted(a) && w.is_deleted(b));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::world::{GameState, Entity};
+
+//what GameState::on_insert/on_replace/on_remove take
+//plain fns on purpose: they get called from wherever the change was made, any thread, so no captured state
+pub type Hook<C> = fn(&GameState, Entity, &C);
+
+//everything registered for one component type, stored (type-erased) in GameState::hooks
+pub(crate) struct Hooks<C> {
+    pub(crate) on_insert: Vec<Hook<C>>,
+    pub(crate) on_replace: Vec<Hook<C>>,
+    pub(crate) on_remove: Vec<Hook<C>>,
+}
+
+impl<C> Hooks<C> {
+    pub(crate) fn new() -> Hooks<C> {
+        Hooks{on_insert: Vec::new(), on_replace: Vec::new(), on_remove: Vec::new()}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::world::{GameState, Component, Entity, Resource};
+    use crate::storage::{VecStorage, NullStorage};
+
+    #[derive(Clone)]
+    struct Armour(i32);
+    impl Component for Armour {
+        type Storage = VecStorage<Self>;
+    }
+    #[derive(Clone)]
+    struct Cursed;
+    impl Component for Cursed {
+        type Storage = NullStorage<Self>;
+    }
+
+    #[derive(Default)]
+    struct Log(Vec<String>);
+    impl Resource for Log {}
+
+    fn log(w: &GameState, line: String) {
+        w.resource_mut::<Log>().0.push(line);
+    }
+    fn inserted(w: &GameState, _: Entity, Armour(a): &Armour) {
+        log(w, format!("insert {}", a));
+    }
+    fn replaced(w: &GameState, _: Entity, Armour(a): &Armour) {
+        log(w, format!("replace {}", a));
+    }
+    //the rest of the entity's still there when this runs, even when the whole thing's being deleted
+    fn removed(w: &GameState, e: Entity, Armour(a): &Armour) {
+        log(w, format!("remove {} cursed={}", a, w.has_flag::<Cursed>(e)));
+    }
+
+    fn prepare_world() -> GameState {
+        let mut w = GameState::new();
+        w.register_component::<Armour>();
+        w.register_component::<Cursed>();
+        w.set_resource(Log::default());
+        w.on_insert::<Armour>(inserted);
+        w.on_replace::<Armour>(replaced);
+        w.on_remove::<Armour>(removed);
+        w
+    }
+
+    fn take_log(w: &GameState) -> Vec<String> {
+        std::mem::take(&mut w.resource_mut::<Log>().0)
+    }
+
+    #[test]
+    fn insert_replace_remove() {
+        let mut w = prepare_world();
+        let a = w.create_entity();
+        w.insert(a, Armour(1));
+        w.insert(a, Armour(2));
+        w.update(a, |Armour(x)| *x += 1); //not a replace
+        w.delete::<Armour>(a);
+        w.delete::<Armour>(a); //already gone
+        assert_eq!(take_log(&w), vec!["insert 1", "replace 1", "insert 2", "remove 3 cursed=false"]);
+
+        let b = w.create_entity();
+        w.insert(b, Cursed);
+        w.insert(b, Armour(4));
+        w.commands().delete::<Armour>(b);
+        w.update_entities();
+        w.delete_entity(a);
+        w.delete_entity(b);
+        w.update_entities();
+        assert_eq!(take_log(&w), vec!["insert 4", "remove 4 cursed=true"]);
+
+        let c = w.create_entity();
+        w.insert(c, Cursed);
+        w.insert(c, Armour(5));
+        w.delete_entity(c);
+        assert_eq!(take_log(&w), vec!["insert 5"]);
+        //nothing actually comes off until it's swept
+        w.update_entities();
+        assert_eq!(take_log(&w), vec!["remove 5 cursed=true"]);
+        assert!(w.is_deleted(c));
+    }
+
+    fn cascade(w: &GameState, _: Entity, Armour(a): &Armour) {
+        //i.e. deleting whatever this was holding together
+        if let Some(&e) = w.resource::<Held>().0.get(*a as usize) {
+            w.delete_entity(e);
+        }
+    }
+    struct Held(Vec<Entity>);
+    impl Resource for Held {}
+
+    #[test]
+    fn hooks_can_delete_more() {
+        let mut w = GameState::new();
+        w.register_component::<Armour>();
+        w.on_remove::<Armour>(cascade);
+        let es: Vec<_> = (0..3).map(|_| w.create_entity()).collect();
+        for (i, &e) in es.iter().enumerate() {
+            w.insert(e, Armour(i as i32 + 1));
+        }
+        w.set_resource(Held(es.clone()));
+        w.delete_entity(es[0]);
+        //one update gets the whole chain
+        w.update_entities();
+        assert!(es.iter().all(|&e| w.is_deleted(e)));
+    }
+}
@@ -0,0 +1,147 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use crate::world::{Component, ComponentStorage};
+
+//secondary indices: which entities have a C that key() turns into some K, see GameState::add_index/lookup
+//Column keeps them up to date, since everything that changes a component goes through it anyway
+//inserts and deletes are dealt with straight away; anything handed out as a &mut (update(), queries) might have
+//changed without anybody saying so, so those get looked at again the next time the index is used
+pub(crate) trait AnyIndex<C>: Send + Sync {
+    fn inserted(&mut self, index: usize, c: &C);
+    fn deleted(&mut self, index: usize);
+    fn touched(&mut self, index: usize);
+    fn touched_all(&mut self);
+    fn as_any(&self) -> &dyn Any;
+}
+
+pub(crate) struct Index<C, K> {
+    key: fn(&C) -> K,
+    state: Mutex<State<K>>, //so lookups can catch up on touched entities with only a read lock on the column
+}
+
+struct State<K> {
+    entities: HashMap<K, Vec<usize>>, //in the order they got that key
+    keys: HashMap<usize, K>,
+    touched: Vec<usize>,
+    all_touched: bool,
+}
+
+impl<K: Eq + Hash + Clone> State<K> {
+    fn set(&mut self, index: usize, key: K) {
+        if self.keys.get(&index) == Some(&key) {
+            return;
+        }
+        self.remove(index);
+        self.entities.entry(key.clone()).or_default().push(index);
+        self.keys.insert(index, key);
+    }
+    fn remove(&mut self, index: usize) {
+        if let Some(old) = self.keys.remove(&index) {
+            let entities = self.entities.get_mut(&old).unwrap();
+            entities.retain(|&e| e != index);
+            if entities.is_empty() {
+                self.entities.remove(&old);
+            }
+        }
+    }
+}
+
+impl<C: Component, K: Eq + Hash + Clone + Send + Sync + 'static> Index<C, K> {
+    pub(crate) fn new(key: fn(&C) -> K, storage: &C::Storage) -> Index<C, K> {
+        let mut state = State{entities: HashMap::new(), keys: HashMap::new(), touched: Vec::new(), all_touched: false};
+        for (i, c) in storage.iter() {
+            state.set(i, key(c));
+        }
+        Index{key, state: Mutex::new(state)}
+    }
+    //entity indices, oldest first
+    pub(crate) fn get(&self, storage: &C::Storage, key: &K) -> Vec<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.all_touched {
+            state.all_touched = false;
+            state.touched = storage.iter().map(|(i, _)| i).collect();
+        }
+        for i in std::mem::take(&mut state.touched) {
+            match storage.get(i) {
+                Some(c) => state.set(i, (self.key)(c)),
+                None => state.remove(i),
+            }
+        }
+        state.entities.get(key).cloned().unwrap_or_default()
+    }
+}
+
+impl<C: Component, K: Eq + Hash + Clone + Send + Sync + 'static> AnyIndex<C> for Index<C, K> {
+    fn inserted(&mut self, index: usize, c: &C) {
+        let key = (self.key)(c);
+        self.state.get_mut().unwrap().set(index, key);
+    }
+    fn deleted(&mut self, index: usize) {
+        self.state.get_mut().unwrap().remove(index);
+    }
+    fn touched(&mut self, index: usize) {
+        let state = self.state.get_mut().unwrap();
+        //past this point it's cheaper to go over everything once than keep a list
+        if state.touched.len() > state.keys.len() {
+            state.all_touched = true;
+            state.touched.clear();
+        } else if !state.all_touched {
+            state.touched.push(index);
+        }
+    }
+    fn touched_all(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        state.all_touched = true;
+        state.touched.clear();
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::world::{GameState, Component};
+    use crate::storage::VecStorage;
+    use crate::error::Error;
+
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    enum Sprite {
+        Player,
+        Enemy,
+    }
+    #[derive(Clone)]
+    struct RenderInfo(Sprite, u32);
+    impl Component for RenderInfo {
+        type Storage = VecStorage<Self>;
+    }
+
+    #[test]
+    fn lookup_by_key() {
+        let mut w = GameState::new();
+        w.register_component::<RenderInfo>();
+        let p = w.create_entity();
+        w.insert(p, RenderInfo(Sprite::Player, 0));
+        //existing components get indexed too
+        w.add_index::<RenderInfo, Sprite>(|r| r.0);
+        let a = w.spawn().with(RenderInfo(Sprite::Enemy, 0)).build();
+        let b = w.spawn().with(RenderInfo(Sprite::Enemy, 1)).build();
+        assert_eq!(w.lookup::<RenderInfo, _>(&Sprite::Enemy), vec![a, b]);
+        assert_eq!(w.lookup::<RenderInfo, _>(&Sprite::Player), vec![p]);
+
+        //changed in place
+        w.update(a, |r: &mut RenderInfo| r.0 = Sprite::Player);
+        assert_eq!(w.lookup::<RenderInfo, _>(&Sprite::Player), vec![p, a]);
+        //changed by a query, and not changed (so b stays where it was)
+        w.query::<(&mut RenderInfo,)>().for_each(|(r,)| r.1 += 1);
+        assert_eq!(w.lookup::<RenderInfo, _>(&Sprite::Enemy), vec![b]);
+        //deleted
+        w.delete::<RenderInfo>(p);
+        w.delete_entity(b);
+        assert_eq!(w.lookup::<RenderInfo, _>(&Sprite::Player), vec![a]);
+        assert!(w.lookup::<RenderInfo, _>(&Sprite::Enemy).is_empty());
+        assert!(matches!(w.try_lookup::<RenderInfo, u32>(&1), Err(Error::MissingIndex(..))));
+    }
+}
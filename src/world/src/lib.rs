@@ -1,8 +1,37 @@
 pub mod world;
 pub mod storage;
+pub mod query;
+pub mod signature;
+pub mod commands;
+pub mod error;
+pub mod borrow;
+pub mod schedule;
+pub mod tracking;
+pub mod events;
+pub mod hooks;
+pub mod hierarchy;
+pub mod names;
+pub mod index;
+pub mod bundle;
+mod pool;
+#[cfg(feature = "serde")]
+pub mod persist;
 
 pub use self::world::GameState;
 pub use self::world::Component;
+pub use self::world::ComponentInfo;
 pub use self::world::ComponentStorage;
 pub use self::world::Entity;
+pub use self::world::EntityTable;
+pub use self::world::Resource;
 pub use self::world::SystemRunner;
+pub use self::query::{Query, QueryBorrow, With, Without, Added, Changed, Res, ResMut};
+pub use self::signature::Signature;
+pub use self::commands::Commands;
+pub use self::error::Error;
+pub use self::schedule::{Schedule, Stage};
+pub use self::events::{Events, EventCursor};
+pub use self::hooks::Hook;
+pub use self::hierarchy::{Parent, Children};
+pub use self::names::Name;
+pub use self::bundle::{Bundle, EntityBuilder};
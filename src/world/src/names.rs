@@ -0,0 +1,51 @@
+use crate::world::{GameState, Component};
+use crate::storage::VecStorage;
+
+//what an entity's called; GameState keeps track of who's called what, see find_by_name/entities_named
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Name(pub String);
+impl Component for Name {
+    type Storage = VecStorage<Self>;
+}
+impl Name {
+    pub fn new(name: impl Into<String>) -> Name {
+        Name(name.into())
+    }
+}
+
+//just an index like any other, see index.rs
+pub(crate) fn init(w: &mut GameState) {
+    w.add_index::<Name, String>(|Name(name)| name.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_by_name() {
+        let mut w = GameState::new();
+        let kay = w.spawn().with(Name::new("kay")).build();
+        let a = w.spawn().with(Name::new("slime")).build();
+        let b = w.create_entity();
+        w.insert(b, Name::new("slime"));
+        assert_eq!(w.find_by_name("kay"), Some(kay));
+        assert_eq!(w.entities_named("slime"), vec![a, b]);
+        assert_eq!(w.find_by_name("nobody"), None);
+
+        //renamed, either way
+        w.insert(a, Name::new(String::from("big slime")));
+        assert_eq!(w.entities_named("slime"), vec![b]);
+        assert_eq!(w.find_by_name("big slime"), Some(a));
+        w.update(a, |Name(name): &mut Name| name.push('!'));
+        assert_eq!(w.find_by_name("big slime!"), Some(a));
+        //deleted, but not swept yet
+        w.delete_entity(b);
+        assert_eq!(w.find_by_name("slime"), None);
+        w.update_entities();
+        let c = w.spawn().with(Name::new("slime")).build();
+        assert_eq!(w.entities_named("slime"), vec![c]);
+        w.delete::<Name>(kay);
+        assert_eq!(w.find_by_name("kay"), None);
+    }
+}
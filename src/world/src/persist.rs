@@ -0,0 +1,85 @@
+//serde support, for saving worlds (only with the "serde" feature)
+//an Entity is written as [index, generation], which means nothing once it's loaded into another world,
+//so anything deserialized inside remap_entities() gets translated to whatever it was loaded as
+use std::cell::RefCell;
+use std::collections::HashMap;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use crate::world::{Entity, EntityTable};
+use crate::hierarchy::{Parent, Children};
+use crate::names::Name;
+
+thread_local! {
+    static ENTITY_MAP: RefCell<Option<HashMap<Entity, Entity>>> = const { RefCell::new(None) };
+}
+
+//i.e. remap_entities(old_to_new, || serde_json::from_value::<Inventory>(v))
+//anything not in the map (i.e. it was already deleted when the save was made) comes out as a handle that's always stale
+pub fn remap_entities<R>(map: HashMap<Entity, Entity>, f: impl FnOnce() -> R) -> R {
+    let outer = ENTITY_MAP.with(|m| m.replace(Some(map)));
+    let r = f();
+    ENTITY_MAP.with(|m| m.replace(outer));
+    r
+}
+
+impl Serialize for Entity {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        (self.index, self.generation).serialize(s)
+    }
+}
+impl<'de> Deserialize<'de> for Entity {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Entity, D::Error> {
+        let (index, generation) = <(usize, usize)>::deserialize(d)?;
+        let saved = Entity{index, generation};
+        Ok(ENTITY_MAP.with(|m| match &*m.borrow() {
+            Some(map) => map.get(&saved).cloned().unwrap_or(Entity{index: usize::MAX, generation: 0}),
+            None => saved,
+        }))
+    }
+}
+
+impl Serialize for EntityTable {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        (&self.generations, &self.free).serialize(s)
+    }
+}
+impl<'de> Deserialize<'de> for EntityTable {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<EntityTable, D::Error> {
+        let (generations, free) = Deserialize::deserialize(d)?;
+        Ok(EntityTable{generations, free})
+    }
+}
+
+//saves leave Children out and rebuild them from Parent as it's loaded,
+//but snapshots don't run hooks, so they need both
+impl Serialize for Parent {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(s)
+    }
+}
+impl<'de> Deserialize<'de> for Parent {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Parent, D::Error> {
+        Entity::deserialize(d).map(Parent)
+    }
+}
+impl Serialize for Children {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(s)
+    }
+}
+impl<'de> Deserialize<'de> for Children {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Children, D::Error> {
+        Vec::deserialize(d).map(Children)
+    }
+}
+
+//just the string, i.e. "Name": "kay"
+impl Serialize for Name {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(s)
+    }
+}
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Name, D::Error> {
+        String::deserialize(d).map(Name)
+    }
+}
@@ -0,0 +1,98 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+//threads that stick around for as long as the Schedule does, so a parallel batch doesn't have to start its own every frame
+pub(crate) struct Pool {
+    jobs: Option<mpsc::Sender<Job>>, //taken when it's dropped, which is what tells the workers to stop
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    //None if the threads can't be started, in which case batches go back to spawning their own
+    pub(crate) fn new(threads: usize) -> Option<Pool> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        let mut pool = Pool{jobs: Some(jobs), workers: Vec::with_capacity(threads)};
+        for n in 0..threads {
+            let queue = queue.clone();
+            let worker = thread::Builder::new().name(format!("schedule worker {}", n)).spawn(move || loop {
+                //the lock's only held while waiting, not while the job runs
+                let job = queue.lock().unwrap().recv();
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => break,
+                }
+            });
+            pool.workers.push(worker.ok()?);
+        }
+        Some(pool)
+    }
+
+    //runs here on this thread and jobs on the workers, and doesn't return until every one of them is done with,
+    //which is what makes it alright for jobs to borrow from the caller (same idea as std::thread::scope)
+    //a panic in any of them gets passed on once the rest have finished
+    pub(crate) fn scope<'a>(&self, here: impl FnOnce(), jobs: impl IntoIterator<Item=Box<dyn FnOnce() + Send + 'a>>) {
+        let (done, finished) = mpsc::channel();
+        for job in jobs {
+            let done = done.clone();
+            let job: Box<dyn FnOnce() + Send + 'a> = Box::new(move || {
+                let _ = done.send(panic::catch_unwind(AssertUnwindSafe(job)));
+            });
+            //safe because of the wait below: the job's either run or been dropped by the time this returns
+            let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Job>(job) };
+            if let Err(mpsc::SendError(job)) = self.jobs.as_ref().unwrap().send(job) {
+                job();
+            }
+        }
+        drop(done);
+        let here = panic::catch_unwind(AssertUnwindSafe(here));
+        //finishes once every job's sender is gone, whether it ran, panicked or got dropped without running
+        let results: Vec<_> = finished.iter().collect();
+        for result in std::iter::once(here).chain(results) {
+            if let Err(e) = result {
+                panic::resume_unwind(e);
+            }
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn borrows_and_panics() {
+        let pool = Pool::new(2).unwrap();
+        let seen = Mutex::new(Vec::new());
+        for _ in 0..3 {
+            let job = || seen.lock().unwrap().push(thread::current().id());
+            let jobs: Vec<Box<dyn FnOnce() + Send>> = vec![Box::new(job), Box::new(job)];
+            pool.scope(job, jobs);
+        }
+        //everything's finished by the time scope returns, and only ever on the same three threads
+        let seen = seen.into_inner().unwrap();
+        assert_eq!(seen.len(), 9);
+        assert!(seen.iter().collect::<HashSet<_>>().len() <= 3);
+
+        //a job panicking comes back out of scope, and the pool still works after
+        let jobs: Vec<Box<dyn FnOnce() + Send>> = vec![Box::new(|| panic!("oops"))];
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| pool.scope(|| (), jobs))).is_err());
+        let ran = Mutex::new(0);
+        let jobs: Vec<Box<dyn FnOnce() + Send>> = vec![Box::new(|| *ran.lock().unwrap() += 1), Box::new(|| *ran.lock().unwrap() += 1)];
+        pool.scope(|| (), jobs);
+        assert_eq!(ran.into_inner().unwrap(), 2);
+    }
+}
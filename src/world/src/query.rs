@@ -0,0 +1,520 @@
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering;
+use crate::world::{GameState, Component, ComponentStorage, Entity, Resource};
+use crate::signature::Signature;
+use crate::borrow::{StorageRef, StorageMut};
+use crate::tracking::Column;
+use crate::schedule::Access;
+
+//anything that can be asked for in a query: &C, &mut C, the filters below, or tuples of those
+//the lock is taken once in lock() and held for the whole iteration,
+//then fetch() hands out references into it for each entity
+pub trait Query {
+    type Lock<'w>;
+    type Item<'l>;
+    #[track_caller]
+    fn lock(w: &GameState) -> Self::Lock<'_>;
+    //whether the entity at index gets through, without touching anything
+    fn matches(lock: &Self::Lock<'_>, index: usize) -> bool;
+    //only for entities that matched, since handing out a &mut C counts as changing it
+    fn fetch<'l>(lock: &'l mut Self::Lock<'_>, index: usize) -> Option<Self::Item<'l>>;
+    //adds whatever components every match must have (so not Option or Without)
+    fn required(_w: &GameState, _signature: &mut Signature) {}
+    //an upper bound on how many entities can match, if this part of the query narrows it down at all
+    fn len(_lock: &Self::Lock<'_>) -> Option<usize> {
+        None
+    }
+    //the entities that can match, in storage order; tuples ask whichever part has the fewest
+    fn matching(_lock: &Self::Lock<'_>, _required: &Signature) -> Option<Vec<usize>> {
+        None
+    }
+    //which storages/resources this reads and writes, so the Schedule knows what can run alongside it
+    fn access(_access: &mut Access) {}
+}
+
+impl<C: Component> Query for &C {
+    type Lock<'w> = StorageRef<'w, Column<C>>;
+    type Item<'l> = &'l C;
+    #[track_caller]
+    fn lock(w: &GameState) -> Self::Lock<'_> {
+        w.lock_read::<C>()
+    }
+    fn matches(lock: &Self::Lock<'_>, index: usize) -> bool {
+        lock.get(index).is_some()
+    }
+    fn fetch<'l>(lock: &'l mut Self::Lock<'_>, index: usize) -> Option<Self::Item<'l>> {
+        lock.get(index)
+    }
+    fn required(w: &GameState, signature: &mut Signature) {
+        signature.insert(w.component_id::<C>());
+    }
+    fn len(lock: &Self::Lock<'_>) -> Option<usize> {
+        Some(lock.len())
+    }
+    fn matching(lock: &Self::Lock<'_>, required: &Signature) -> Option<Vec<usize>> {
+        Some(lock.matching(required))
+    }
+    fn access(access: &mut Access) {
+        access.read::<C>();
+    }
+}
+
+//along with the tick to stamp everything it hands out as changed
+impl<C: Component> Query for &mut C {
+    type Lock<'w> = (StorageMut<'w, Column<C>>, u64);
+    type Item<'l> = &'l mut C;
+    #[track_caller]
+    fn lock(w: &GameState) -> Self::Lock<'_> {
+        (w.lock_write::<C>(), w.this_run())
+    }
+    fn matches(lock: &Self::Lock<'_>, index: usize) -> bool {
+        lock.0.get(index).is_some()
+    }
+    fn fetch<'l>(lock: &'l mut Self::Lock<'_>, index: usize) -> Option<Self::Item<'l>> {
+        lock.0.get_mut_tracked(index, lock.1)
+    }
+    fn required(w: &GameState, signature: &mut Signature) {
+        signature.insert(w.component_id::<C>());
+    }
+    fn len(lock: &Self::Lock<'_>) -> Option<usize> {
+        Some(lock.0.len())
+    }
+    fn matching(lock: &Self::Lock<'_>, required: &Signature) -> Option<Vec<usize>> {
+        Some(lock.0.matching(required))
+    }
+    fn access(access: &mut Access) {
+        access.write::<C>();
+    }
+}
+
+//filters: only match entities that do/don't have C, without handing it out
+//i.e. w.query::<(With<Player>, &mut Position)>().for_each(|(_, pos)| { ... });
+pub struct With<C>(PhantomData<C>);
+pub struct Without<C>(PhantomData<C>);
+
+impl<C: Component> Query for With<C> {
+    type Lock<'w> = StorageRef<'w, Column<C>>;
+    type Item<'l> = ();
+    #[track_caller]
+    fn lock(w: &GameState) -> Self::Lock<'_> {
+        w.lock_read::<C>()
+    }
+    fn matches(lock: &Self::Lock<'_>, index: usize) -> bool {
+        lock.get(index).is_some()
+    }
+    fn fetch<'l>(lock: &'l mut Self::Lock<'_>, index: usize) -> Option<Self::Item<'l>> {
+        Self::matches(lock, index).then_some(())
+    }
+    fn required(w: &GameState, signature: &mut Signature) {
+        signature.insert(w.component_id::<C>());
+    }
+    fn len(lock: &Self::Lock<'_>) -> Option<usize> {
+        Some(lock.len())
+    }
+    fn matching(lock: &Self::Lock<'_>, required: &Signature) -> Option<Vec<usize>> {
+        Some(lock.matching(required))
+    }
+    fn access(access: &mut Access) {
+        access.read::<C>();
+    }
+}
+
+impl<C: Component> Query for Without<C> {
+    type Lock<'w> = StorageRef<'w, Column<C>>;
+    type Item<'l> = ();
+    #[track_caller]
+    fn lock(w: &GameState) -> Self::Lock<'_> {
+        w.lock_read::<C>()
+    }
+    fn matches(lock: &Self::Lock<'_>, index: usize) -> bool {
+        lock.get(index).is_none()
+    }
+    fn fetch<'l>(lock: &'l mut Self::Lock<'_>, index: usize) -> Option<Self::Item<'l>> {
+        Self::matches(lock, index).then_some(())
+    }
+    fn access(access: &mut Access) {
+        access.read::<C>();
+    }
+}
+
+//change filters: only match entities whose C was added/changed (inserted, or handed out as &mut)
+//since this system last ran; outside of a Schedule that's since the very start
+//i.e. w.query::<(Changed<Position>, &Position, &mut RenderLayer)>()
+pub struct Added<C>(PhantomData<C>);
+pub struct Changed<C>(PhantomData<C>);
+
+impl<C: Component> Query for Added<C> {
+    type Lock<'w> = (StorageRef<'w, Column<C>>, u64);
+    type Item<'l> = ();
+    #[track_caller]
+    fn lock(w: &GameState) -> Self::Lock<'_> {
+        (w.lock_read::<C>(), w.last_run())
+    }
+    fn matches(lock: &Self::Lock<'_>, index: usize) -> bool {
+        lock.0.added_since(index, lock.1)
+    }
+    fn fetch<'l>(lock: &'l mut Self::Lock<'_>, index: usize) -> Option<Self::Item<'l>> {
+        Self::matches(lock, index).then_some(())
+    }
+    fn required(w: &GameState, signature: &mut Signature) {
+        signature.insert(w.component_id::<C>());
+    }
+    fn len(lock: &Self::Lock<'_>) -> Option<usize> {
+        Some(lock.0.len())
+    }
+    fn matching(lock: &Self::Lock<'_>, required: &Signature) -> Option<Vec<usize>> {
+        Some(lock.0.matching(required))
+    }
+    fn access(access: &mut Access) {
+        access.read::<C>();
+    }
+}
+
+impl<C: Component> Query for Changed<C> {
+    type Lock<'w> = (StorageRef<'w, Column<C>>, u64);
+    type Item<'l> = ();
+    #[track_caller]
+    fn lock(w: &GameState) -> Self::Lock<'_> {
+        (w.lock_read::<C>(), w.last_run())
+    }
+    fn matches(lock: &Self::Lock<'_>, index: usize) -> bool {
+        lock.0.changed_since(index, lock.1)
+    }
+    fn fetch<'l>(lock: &'l mut Self::Lock<'_>, index: usize) -> Option<Self::Item<'l>> {
+        Self::matches(lock, index).then_some(())
+    }
+    fn required(w: &GameState, signature: &mut Signature) {
+        signature.insert(w.component_id::<C>());
+    }
+    fn len(lock: &Self::Lock<'_>) -> Option<usize> {
+        Some(lock.0.len())
+    }
+    fn matching(lock: &Self::Lock<'_>, required: &Signature) -> Option<Vec<usize>> {
+        Some(lock.0.matching(required))
+    }
+    fn access(access: &mut Access) {
+        access.read::<C>();
+    }
+}
+
+//resources, handed to every match alongside the components
+//i.e. w.query::<(Res<Gravity>, &mut Velocity)>().for_each(|(g, vel)| { ... });
+pub struct Res<R>(PhantomData<R>);
+pub struct ResMut<R>(PhantomData<R>);
+
+impl<R: Resource> Query for Res<R> {
+    type Lock<'w> = StorageRef<'w, R>;
+    type Item<'l> = &'l R;
+    #[track_caller]
+    fn lock(w: &GameState) -> Self::Lock<'_> {
+        w.resource::<R>()
+    }
+    fn matches(_lock: &Self::Lock<'_>, _index: usize) -> bool {
+        true
+    }
+    fn fetch<'l>(lock: &'l mut Self::Lock<'_>, _index: usize) -> Option<Self::Item<'l>> {
+        Some(lock)
+    }
+    fn access(access: &mut Access) {
+        access.read::<R>();
+    }
+}
+
+impl<R: Resource> Query for ResMut<R> {
+    type Lock<'w> = StorageMut<'w, R>;
+    type Item<'l> = &'l mut R;
+    #[track_caller]
+    fn lock(w: &GameState) -> Self::Lock<'_> {
+        w.resource_mut::<R>()
+    }
+    fn matches(_lock: &Self::Lock<'_>, _index: usize) -> bool {
+        true
+    }
+    fn fetch<'l>(lock: &'l mut Self::Lock<'_>, _index: usize) -> Option<Self::Item<'l>> {
+        Some(lock)
+    }
+    fn access(access: &mut Access) {
+        access.write::<R>();
+    }
+}
+
+//optional components never filter anything out, they're just None when missing
+impl<Q: Query> Query for Option<Q> {
+    type Lock<'w> = Q::Lock<'w>;
+    type Item<'l> = Option<Q::Item<'l>>;
+    #[track_caller]
+    fn lock(w: &GameState) -> Self::Lock<'_> {
+        Q::lock(w)
+    }
+    fn matches(_lock: &Self::Lock<'_>, _index: usize) -> bool {
+        true
+    }
+    fn fetch<'l>(lock: &'l mut Self::Lock<'_>, index: usize) -> Option<Self::Item<'l>> {
+        Some(if Q::matches(lock, index) { Q::fetch(lock, index) } else { None })
+    }
+    fn access(access: &mut Access) {
+        Q::access(access);
+    }
+}
+
+macro_rules! impl_query {
+    ($($tp:ident),*) => (
+        impl<$($tp: Query),*> Query for ($($tp,)*) {
+            type Lock<'w> = ($($tp::Lock<'w>,)*);
+            type Item<'l> = ($($tp::Item<'l>,)*);
+            #[track_caller]
+            fn lock(w: &GameState) -> Self::Lock<'_> {
+                ($($tp::lock(w),)*)
+            }
+            #[allow(non_snake_case)] //same trick as impl_system
+            fn matches(lock: &Self::Lock<'_>, index: usize) -> bool {
+                let ($($tp,)*) = lock;
+                true $(&& $tp::matches($tp, index))*
+            }
+            //every part gets checked before any of them hands anything out, so a &mut C early on
+            //doesn't mark an entity as changed when something later on turns it away
+            #[allow(non_snake_case)]
+            fn fetch<'l>(lock: &'l mut Self::Lock<'_>, index: usize) -> Option<Self::Item<'l>> {
+                if !Self::matches(lock, index) {
+                    return None;
+                }
+                let ($($tp,)*) = lock;
+                Some(($($tp::fetch($tp, index)?,)*))
+            }
+            fn required(w: &GameState, signature: &mut Signature) {
+                $($tp::required(w, signature);)*
+            }
+            #[allow(non_snake_case)]
+            fn len(lock: &Self::Lock<'_>) -> Option<usize> {
+                let ($($tp,)*) = lock;
+                [$($tp::len($tp)),*].iter().flatten().cloned().min()
+            }
+            #[allow(non_snake_case)]
+            fn matching(lock: &Self::Lock<'_>, required: &Signature) -> Option<Vec<usize>> {
+                let smallest = Self::len(lock)?;
+                let ($($tp,)*) = lock;
+                $(if $tp::len($tp) == Some(smallest) {
+                    return $tp::matching($tp, required);
+                })*
+                None
+            }
+            fn access(access: &mut Access) {
+                $($tp::access(access);)*
+            }
+        }
+    );
+}
+
+impl_query!(A);
+impl_query!(A, B);
+impl_query!(A, B, C);
+impl_query!(A, B, C, D);
+impl_query!(A, B, C, D, E);
+impl_query!(A, B, C, D, E, F);
+impl_query!(A, B, C, D, E, F, G);
+impl_query!(A, B, C, D, E, F, G, H);
+
+//holds the locks for a query until it's dropped
+//so don't go touching the same components through the GameState while one of these is alive
+pub struct QueryBorrow<'w, Q: Query> {
+    world: &'w GameState,
+    lock: Q::Lock<'w>,
+    required: Signature,
+}
+
+impl<'w, Q: Query> QueryBorrow<'w, Q> {
+    #[track_caller]
+    pub(crate) fn new(world: &'w GameState) -> QueryBorrow<'w, Q> {
+        let mut required = Signature::new();
+        Q::required(world, &mut required);
+        QueryBorrow{world, lock: Q::lock(world), required}
+    }
+
+    //runs f on every living entity that has everything in Q
+    pub fn for_each(&mut self, mut f: impl FnMut(Q::Item<'_>)) {
+        self.for_each_entity(|_, item| f(item));
+    }
+    pub fn for_each_entity(&mut self, mut f: impl FnMut(Entity, Q::Item<'_>)) {
+        //walk whichever storage has the fewest entities in it (archetypal ones skip non-matching tables too)
+        //only a query made entirely of Option/Without has to check every entity
+        let order = Q::matching(&self.lock, &self.required)
+            .unwrap_or_else(|| (0..self.world.entities_size.load(Ordering::SeqCst)).collect());
+        for i in order {
+            let e = self.world.entity_at(i);
+            if self.world.is_alive(e) && Q::matches(&self.lock, i) {
+                if let Some(item) = Q::fetch(&mut self.lock, i) {
+                    f(e, item);
+                }
+            }
+        }
+    }
+    //just the one entity, if it's alive and matches
+    pub fn get(&mut self, entity: Entity, f: impl FnOnce(Q::Item<'_>)) {
+        if self.world.is_alive(entity) && Q::matches(&self.lock, entity.id()) {
+            if let Some(item) = Q::fetch(&mut self.lock, entity.id()) {
+                f(item);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{GameState, Component};
+    use crate::storage::{VecStorage, HashMapStorage, ArchetypeStorage};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Position(i32);
+    impl Component for Position {
+        type Storage = VecStorage<Self>;
+    }
+    #[derive(Clone, Debug, PartialEq)]
+    struct Velocity(i32);
+    impl Component for Velocity {
+        type Storage = HashMapStorage<Self>;
+    }
+
+    #[derive(Clone)]
+    struct Player;
+    impl Component for Player {
+        type Storage = HashMapStorage<Self>;
+    }
+
+    fn prepare_world() -> GameState {
+        let mut w = GameState::new();
+        w.register_component::<Position>();
+        w.register_component::<Velocity>();
+        w.register_component::<Player>();
+        w
+    }
+
+    #[test]
+    fn mutable_and_shared() {
+        let w = prepare_world();
+        let a = w.create_entity();
+        w.insert(a, Position(0));
+        w.insert(a, Velocity(2));
+        let b = w.create_entity();
+        w.insert(b, Position(10));
+        w.query::<(&mut Position, &Velocity)>().for_each(|(pos, vel)| {
+            pos.0 += vel.0;
+        });
+        assert_eq!(w.clone::<Position>(a), Some(Position(2)));
+        //b has no Velocity so it's skipped
+        assert_eq!(w.clone::<Position>(b), Some(Position(10)));
+    }
+
+    #[test]
+    fn skips_deleted() {
+        let w = prepare_world();
+        let a = w.create_entity();
+        w.insert(a, Position(0));
+        let b = w.create_entity();
+        w.insert(b, Position(0));
+        w.delete_entity(a);
+        let mut seen = Vec::new();
+        w.query::<(&Position,)>().for_each_entity(|e, _| seen.push(e));
+        assert_eq!(seen, vec![b]);
+        let mut q = w.query::<(&mut Position,)>();
+        q.get(b, |(pos,)| pos.0 = 5);
+        q.get(a, |(pos,)| pos.0 = 5);
+        drop(q);
+        assert_eq!(w.clone::<Position>(b), Some(Position(5)));
+    }
+
+    #[test]
+    fn filters() {
+        let w = prepare_world();
+        let player = w.create_entity();
+        w.insert(player, Position(0));
+        w.insert(player, Player);
+        let other = w.create_entity();
+        w.insert(other, Position(0));
+        w.insert(other, Velocity(3));
+        w.query::<(With<Player>, &mut Position)>().for_each(|(_, pos)| pos.0 = 1);
+        w.query::<(Without<Player>, &mut Position)>().for_each(|(_, pos)| pos.0 = 2);
+        assert_eq!(w.clone::<Position>(player), Some(Position(1)));
+        assert_eq!(w.clone::<Position>(other), Some(Position(2)));
+        //both still match, with or without a Velocity
+        let mut matched = 0;
+        w.query::<(&mut Position, Option<&Velocity>)>().for_each(|(pos, vel)| {
+            pos.0 += vel.map_or(0, |v| v.0);
+            matched += 1;
+        });
+        assert_eq!(matched, 2);
+        assert_eq!(w.clone::<Position>(player), Some(Position(1)));
+        assert_eq!(w.clone::<Position>(other), Some(Position(5)));
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Packed(i32);
+    impl Component for Packed {
+        type Storage = ArchetypeStorage<Self>;
+    }
+
+    #[test]
+    fn archetype_order() {
+        let mut w = prepare_world();
+        w.register_component::<Packed>();
+        let mut with_vel = Vec::new();
+        for i in 0..6 {
+            let e = w.create_entity();
+            w.insert(e, Packed(i));
+            if i % 2 == 0 {
+                w.insert(e, Velocity(i));
+                with_vel.push(e);
+            }
+        }
+        //only 3 Velocities vs 6 Packed, so this one's driven by the HashMap and comes out in any order
+        let mut seen = Vec::new();
+        w.query::<(&Packed, &Velocity)>().for_each_entity(|e, (p, v)| {
+            assert_eq!(p.0, v.0);
+            seen.push(e);
+        });
+        seen.sort();
+        assert_eq!(seen, with_vel);
+        let mut missing = Vec::new();
+        w.query::<(&Packed, Without<Velocity>)>().for_each_entity(|e, (p, _)| missing.push((e, p.0)));
+        //the odd ones grow a Velocity later, so they end up packed after the even ones
+        for &(e, v) in &missing {
+            w.insert(e, Velocity(v));
+        }
+        let mut seen = Vec::new();
+        w.query::<(&Packed, &Velocity)>().for_each_entity(|e, (p, v)| {
+            assert_eq!(p.0, v.0);
+            seen.push(e);
+        });
+        with_vel.extend(missing.iter().map(|&(e, _)| e));
+        assert_eq!(seen, with_vel);
+    }
+
+    //deliberately not Clone
+    struct Gravity(i32);
+    impl Resource for Gravity {}
+    struct Moved(usize);
+    impl Resource for Moved {}
+
+    #[test]
+    fn resources() {
+        let mut w = prepare_world();
+        w.set_resource(Gravity(3));
+        w.set_resource(Moved(0));
+        for _ in 0..2 {
+            let e = w.create_entity();
+            w.insert(e, Velocity(0));
+        }
+        w.query::<(Res<Gravity>, ResMut<Moved>, &mut Velocity)>().for_each(|(g, moved, vel)| {
+            vel.0 += g.0;
+            moved.0 += 1;
+        });
+        assert_eq!(w.resource::<Moved>().0, 2);
+        w.resource_mut::<Gravity>().0 = -1;
+        w.query::<(Res<Gravity>, &mut Velocity)>().for_each(|(g, vel)| vel.0 += g.0);
+        let mut total = 0;
+        w.read_all(|_, v: &Velocity| total += v.0);
+        assert_eq!(total, 4);
+        assert_eq!(w.remove_resource::<Gravity>().map(|g| g.0), Some(-1));
+        assert!(w.try_resource::<Gravity>().is_err());
+    }
+}
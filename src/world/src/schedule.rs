@@ -0,0 +1,398 @@
+use std::any::TypeId;
+use std::collections::HashSet;
+use crate::world::GameState;
+use crate::query::Query;
+use crate::error::Error;
+use crate::pool::Pool;
+
+//stages run in this order, and update_entities() runs after each one
+//so anything queued up through Commands in one stage is in place for the next
+//(update_events() runs once at the end of the whole thing, see Schedule::run)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Stage {
+    PreUpdate,
+    Update,
+    PostUpdate,
+    Render,
+}
+const STAGES: [Stage; 4] = [Stage::PreUpdate, Stage::Update, Stage::PostUpdate, Stage::Render];
+
+//what a system touches, by component/resource type
+//systems whose accesses don't conflict (nobody writes what the other reads or writes) can run at the same time
+//a system that never says is assumed to touch everything, so it always runs on its own
+//note: structural changes (insert/delete on other components) aren't covered by this, use Commands for those
+#[derive(Clone, Default, Debug)]
+pub struct Access {
+    reads: HashSet<TypeId>,
+    writes: HashSet<TypeId>,
+    everything: bool,
+}
+
+impl Access {
+    pub fn read<T: 'static>(&mut self) {
+        self.reads.insert(TypeId::of::<T>());
+    }
+    pub fn write<T: 'static>(&mut self) {
+        self.writes.insert(TypeId::of::<T>());
+    }
+    pub fn conflicts_with(&self, other: &Access) -> bool {
+        self.everything || other.everything
+            || self.writes.iter().any(|t| other.reads.contains(t) || other.writes.contains(t))
+            || other.writes.iter().any(|t| self.reads.contains(t))
+    }
+}
+
+type SystemFn<'s> = dyn FnMut(&GameState) + Send + 's;
+
+enum Run<'s> {
+    Parallel(Box<SystemFn<'s>>),
+    //for systems that can't leave the thread that calls run(), i.e. anything holding onto the sdl canvas
+    Local(Box<dyn FnMut(&GameState) + 's>),
+}
+
+struct System<'s> {
+    name: &'static str,
+    stage: Stage,
+    before: Vec<&'static str>,
+    after: Vec<&'static str>,
+    access: Access,
+    run: Run<'s>,
+    last_run: u64, //change tick from when it last started, for Added/Changed/removed()
+}
+
+//all the systems that make up a frame, i.e.
+//  schedule.add_system("bounce", Stage::Update, bounce).before("physics");
+//  schedule.run(&mut w);
+//within a stage systems run in the order they were added, unless before/after says otherwise,
+//or unless they declare what they access and don't get in each other's way, in which case they share the cores
+pub struct Schedule<'s> {
+    systems: Vec<System<'s>>,
+    plan: Option<Plan>, //worked out on the first run after anything's added
+    pool: Option<Pool>, //started the first time there's a batch to share out
+}
+
+struct Plan {
+    order: Vec<usize>, //indices into systems
+    batches: Vec<Vec<Vec<usize>>>, //per stage, groups of systems that can all run at once
+}
+
+//returned by add_system, for tacking on ordering constraints and what it accesses
+pub struct SystemConfig<'a, 's> {
+    system: &'a mut System<'s>,
+}
+
+impl SystemConfig<'_, '_> {
+    pub fn before(self, name: &'static str) -> Self {
+        self.system.before.push(name);
+        self
+    }
+    pub fn after(self, name: &'static str) -> Self {
+        self.system.after.push(name);
+        self
+    }
+    //i.e. .access::<(&mut Position, &Velocity)>() for a system that runs that query
+    pub fn access<Q: Query>(self) -> Self {
+        Q::access(&mut self.system.access);
+        self.declared()
+    }
+    pub fn reads<T: 'static>(self) -> Self {
+        self.system.access.read::<T>();
+        self.declared()
+    }
+    pub fn writes<T: 'static>(self) -> Self {
+        self.system.access.write::<T>();
+        self.declared()
+    }
+    fn declared(self) -> Self {
+        if let Run::Parallel(_) = self.system.run {
+            self.system.access.everything = false;
+        }
+        self
+    }
+}
+
+impl<'s> Schedule<'s> {
+    pub fn new() -> Schedule<'s> {
+        Schedule{systems: Vec::new(), plan: None, pool: None}
+    }
+
+    pub fn add_system(&mut self, name: &'static str, stage: Stage, f: impl FnMut(&GameState) + Send + 's) -> SystemConfig<'_, 's> {
+        self.push(name, stage, Run::Parallel(Box::new(f)))
+    }
+    //always runs by itself on the calling thread, whatever it says it accesses
+    pub fn add_local_system(&mut self, name: &'static str, stage: Stage, f: impl FnMut(&GameState) + 's) -> SystemConfig<'_, 's> {
+        self.push(name, stage, Run::Local(Box::new(f)))
+    }
+    fn push(&mut self, name: &'static str, stage: Stage, run: Run<'s>) -> SystemConfig<'_, 's> {
+        self.plan = None;
+        let access = Access{everything: true, ..Access::default()};
+        self.systems.push(System{name, stage, before: Vec::new(), after: Vec::new(), access, run, last_run: 0});
+        SystemConfig{system: self.systems.last_mut().unwrap()}
+    }
+
+    //the names of every system in the order they'll run, i.e. for checking the constraints all line up
+    //(systems in the same batch might actually run at the same time)
+    pub fn order(&mut self) -> Result<Vec<&'static str>, Error> {
+        self.plan()?;
+        Ok(self.plan.as_ref().unwrap().order.iter().map(|&i| self.systems[i].name).collect())
+    }
+    //same, but grouped into the batches that run in parallel
+    pub fn batches(&mut self) -> Result<Vec<Vec<&'static str>>, Error> {
+        self.plan()?;
+        let plan = self.plan.as_ref().unwrap();
+        Ok(plan.batches.iter().flatten().map(|batch| batch.iter().map(|&i| self.systems[i].name).collect()).collect())
+    }
+    fn plan(&mut self) -> Result<(), Error> {
+        if self.plan.is_none() {
+            let order = self.sort()?;
+            let batches = STAGES.iter().map(|&stage| self.batch(&order, stage)).collect();
+            self.plan = Some(Plan{order, batches});
+        }
+        Ok(())
+    }
+
+    #[track_caller]
+    pub fn run(&mut self, w: &mut GameState) {
+        self.run_stages(w, &STAGES);
+        w.update_events();
+    }
+    //just some of the stages, i.e. the simulation ones a fixed number of times a second and Render once a frame
+    //update_events() is left up to the caller, since only they know how often the events' readers get a look in
+    #[track_caller]
+    pub fn run_stages(&mut self, w: &mut GameState, stages: &[Stage]) {
+        if let Err(e) = self.plan() {
+            panic!("{}", e);
+        }
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let plan = self.plan.as_ref().unwrap();
+        if self.pool.is_none() && threads > 1 && plan.batches.iter().flatten().any(|batch| batch.len() > 1) {
+            self.pool = Pool::new(threads - 1);
+        }
+        let (systems, pool) = (&mut self.systems, self.pool.as_ref());
+        for (_, stage) in STAGES.iter().zip(&plan.batches).filter(|(s, _)| stages.contains(s)) {
+            for batch in stage {
+                if let [i] = batch[..] {
+                    let System{run, last_run, ..} = &mut systems[i];
+                    match run {
+                        Run::Parallel(f) => w.run_system(last_run, f),
+                        Run::Local(f) => w.run_system(last_run, f),
+                    }
+                    continue;
+                }
+                //only Parallel systems ever share a batch
+                let mut fns: Vec<(&mut SystemFn<'s>, &mut u64)> = systems.iter_mut().enumerate()
+                    .filter(|(i, _)| batch.contains(i))
+                    .filter_map(|(_, s)| match &mut s.run {
+                        Run::Parallel(f) => Some((&mut **f, &mut s.last_run)),
+                        Run::Local(_) => None,
+                    })
+                    .collect();
+                let w = &*w;
+                let mut chunks = fns.chunks_mut(batch.len().div_ceil(threads));
+                let here = chunks.next().unwrap();
+                let mut here = move || here.iter_mut().for_each(|(f, last_run)| w.run_system(last_run, f));
+                let jobs = chunks.map(|chunk| move || chunk.iter_mut().for_each(|(f, last_run)| w.run_system(last_run, f)));
+                match pool {
+                    Some(pool) => pool.scope(here, jobs.map(|job| Box::new(job) as Box<dyn FnOnce() + Send>)),
+                    //no pool if the threads for it couldn't be started
+                    None => std::thread::scope(|scope| {
+                        jobs.for_each(|job| { scope.spawn(job); });
+                        here();
+                    }),
+                }
+            }
+            w.update_entities();
+        }
+        //every system's seen whatever was removed up to the earliest of their last runs (including the ones that didn't run this time)
+        if let Some(oldest) = systems.iter().map(|s| s.last_run).min() {
+            w.forget_removed(oldest);
+        }
+    }
+
+    fn find(&self, name: &'static str) -> Result<usize, Error> {
+        self.systems.iter().position(|s| s.name == name).ok_or(Error::UnknownSystem(name))
+    }
+
+    //splits one stage's part of the order into batches that can run at once
+    //a system can join a batch if everything it comes after has already run,
+    //and it doesn't conflict with anything in the batch or anything ahead of it that's still waiting
+    fn batch(&self, order: &[usize], stage: Stage) -> Vec<Vec<usize>> {
+        let mut waiting: Vec<usize> = order.iter().cloned().filter(|&i| self.systems[i].stage == stage).collect();
+        let mut done = vec![false; self.systems.len()];
+        let mut batches = Vec::new();
+        while !waiting.is_empty() {
+            let (mut batch, mut skipped) = (Vec::new(), Vec::new());
+            for i in waiting {
+                let ready = self.after(i).all(|j| done[j] || self.systems[j].stage != stage);
+                let clear = batch.iter().chain(skipped.iter()).all(|&j: &usize| !self.systems[i].access.conflicts_with(&self.systems[j].access));
+                if ready && clear {
+                    batch.push(i);
+                } else {
+                    skipped.push(i);
+                }
+            }
+            for &i in &batch {
+                done[i] = true;
+            }
+            batches.push(batch);
+            waiting = skipped;
+        }
+        batches
+    }
+    //everything system i has to wait for (names are already known to be good by the time this is used)
+    fn after(&self, i: usize) -> impl Iterator<Item=usize> + '_ {
+        let afters = self.systems[i].after.iter().filter_map(move |&name| self.find(name).ok());
+        let befores = self.systems.iter().enumerate()
+            .filter(move |(_, s)| s.before.contains(&self.systems[i].name))
+            .map(|(j, _)| j);
+        afters.chain(befores)
+    }
+
+    //topological sort per stage; whenever there's a choice, the one added first goes first
+    fn sort(&self) -> Result<Vec<usize>, Error> {
+        let n = self.systems.len();
+        for (i, s) in self.systems.iter().enumerate() {
+            if self.find(s.name)? != i {
+                return Err(Error::DuplicateSystem(s.name));
+            }
+        }
+        //edges[a] holds everything that has to wait for a
+        let mut edges = vec![Vec::new(); n];
+        let mut waiting_on = vec![0; n];
+        for (i, s) in self.systems.iter().enumerate() {
+            let afters = s.after.iter().map(|&name| Ok((self.find(name)?, i)));
+            let befores = s.before.iter().map(|&name| Ok((i, self.find(name)?)));
+            for edge in afters.chain(befores) {
+                let (first, then) = edge?;
+                let (first_stage, then_stage) = (self.systems[first].stage, self.systems[then].stage);
+                if first_stage > then_stage {
+                    return Err(Error::UnsatisfiableOrder(s.name));
+                }
+                //different stages already run in the right order
+                if first_stage == then_stage {
+                    edges[first].push(then);
+                    waiting_on[then] += 1;
+                }
+            }
+        }
+        let mut order = Vec::with_capacity(n);
+        let mut done = vec![false; n];
+        for stage in STAGES.iter() {
+            while let Some(next) = (0..n).find(|&i| !done[i] && self.systems[i].stage == *stage && waiting_on[i] == 0) {
+                done[next] = true;
+                order.push(next);
+                for &then in &edges[next] {
+                    waiting_on[then] -= 1;
+                }
+            }
+            //anything left over in this stage is stuck in a cycle
+            if let Some(stuck) = (0..n).find(|&i| !done[i] && self.systems[i].stage == *stage) {
+                return Err(Error::UnsatisfiableOrder(self.systems[stuck].name));
+            }
+        }
+        Ok(order)
+    }
+}
+
+impl Default for Schedule<'_> {
+    fn default() -> Self {
+        Schedule::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::sync::Mutex;
+    use std::thread::{self, ThreadId};
+    use crate::world::Component;
+    use crate::storage::VecStorage;
+
+    #[derive(Clone)]
+    struct Position;
+    impl Component for Position {
+        type Storage = VecStorage<Self>;
+    }
+    #[derive(Clone)]
+    struct Velocity;
+    impl Component for Velocity {
+        type Storage = VecStorage<Self>;
+    }
+
+    #[test]
+    fn stages_and_constraints() {
+        let ran = RefCell::new(Vec::new());
+        let mut schedule = Schedule::new();
+        schedule.add_local_system("render", Stage::Render, |_| ran.borrow_mut().push("render"));
+        schedule.add_local_system("physics", Stage::PostUpdate, |_| ran.borrow_mut().push("physics"));
+        schedule.add_local_system("ai", Stage::Update, |_| ran.borrow_mut().push("ai")).after("input");
+        schedule.add_local_system("bounce", Stage::Update, |_| ran.borrow_mut().push("bounce")).before("physics");
+        schedule.add_local_system("input", Stage::Update, |_| ran.borrow_mut().push("input"));
+        assert_eq!(schedule.order(), Ok(vec!["bounce", "input", "ai", "physics", "render"]));
+        let mut w = GameState::new();
+        schedule.run(&mut w);
+        schedule.run(&mut w);
+        assert_eq!(ran.borrow().len(), 10);
+        assert_eq!(ran.borrow()[..5], ["bounce", "input", "ai", "physics", "render"]);
+        ran.borrow_mut().clear();
+        schedule.run_stages(&mut w, &[Stage::Render, Stage::PostUpdate]);
+        assert_eq!(ran.borrow()[..], ["physics", "render"]);
+    }
+
+    #[test]
+    fn bad_constraints() {
+        let mut schedule = Schedule::new();
+        schedule.add_system("a", Stage::Update, |_| ()).after("b");
+        schedule.add_system("b", Stage::Update, |_| ()).after("a");
+        assert_eq!(schedule.order(), Err(Error::UnsatisfiableOrder("a")));
+        let mut schedule = Schedule::new();
+        schedule.add_system("a", Stage::Update, |_| ()).after("b");
+        schedule.add_system("b", Stage::Render, |_| ());
+        assert_eq!(schedule.order(), Err(Error::UnsatisfiableOrder("a")));
+        let mut schedule = Schedule::new();
+        schedule.add_system("c", Stage::Update, |_| ()).before("nope");
+        assert_eq!(schedule.order(), Err(Error::UnknownSystem("nope")));
+        let mut schedule = Schedule::new();
+        schedule.add_system("a", Stage::Update, |_| ());
+        schedule.add_system("a", Stage::Update, |_| ());
+        assert_eq!(schedule.order(), Err(Error::DuplicateSystem("a")));
+    }
+
+    #[test]
+    fn parallel_batches() {
+        let threads = Mutex::new(Vec::<(&str, ThreadId)>::new());
+        let ran_on = |name| {
+            let threads = &threads;
+            move |_: &GameState| threads.lock().unwrap().push((name, thread::current().id()))
+        };
+        let mut schedule = Schedule::new();
+        schedule.add_system("move", Stage::Update, ran_on("move")).access::<(&mut Position, &Velocity)>();
+        schedule.add_system("tally", Stage::Update, ran_on("tally")).reads::<Velocity>();
+        schedule.add_system("damp", Stage::Update, ran_on("damp")).writes::<Velocity>();
+        schedule.add_system("count", Stage::Update, ran_on("count")).reads::<Position>().after("move");
+        schedule.add_system("anything", Stage::Update, ran_on("anything"));
+        //damp writes the Velocity that move and tally read, so it waits for them; count waits for move because it said so
+        assert_eq!(schedule.batches(), Ok(vec![vec!["move", "tally"], vec!["damp", "count"], vec!["anything"]]));
+        let mut w = GameState::new();
+        w.register_component::<Position>();
+        w.register_component::<Velocity>();
+        for _ in 0..3 {
+            schedule.run(&mut w);
+        }
+        drop(schedule);
+        let threads = threads.into_inner().unwrap();
+        assert_eq!(threads.len(), 15);
+        let first = |name| threads.iter().position(|&(n, _)| n == name).unwrap();
+        assert!(first("move") < first("damp"));
+        assert!(first("move") < first("count"));
+        assert_eq!(threads.last().unwrap().0, "anything");
+        //with more than one core, batched systems get split across threads
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        if cores > 1 {
+            assert_ne!(threads[first("move")].1, threads[first("tally")].1);
+        }
+        //by the same threads every time, rather than new ones for every batch
+        assert!(threads.iter().map(|&(_, id)| id).collect::<HashSet<_>>().len() <= cores);
+    }
+}
@@ -0,0 +1,73 @@
+//which component types an entity has, one bit per registered component
+//grows as needed so there's no cap on how many components can be registered
+//(trailing empty words are always trimmed, so equal sets compare/hash equal)
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Signature {
+    bits: Vec<u64>,
+}
+
+impl Signature {
+    pub fn new() -> Signature {
+        Signature{bits: Vec::new()}
+    }
+    pub fn insert(&mut self, id: usize) {
+        let (word, bit) = (id / 64, id % 64);
+        if word >= self.bits.len() {
+            self.bits.resize(word + 1, 0);
+        }
+        self.bits[word] |= 1 << bit;
+    }
+    pub fn remove(&mut self, id: usize) {
+        let (word, bit) = (id / 64, id % 64);
+        if word < self.bits.len() {
+            self.bits[word] &= !(1 << bit);
+            while self.bits.last() == Some(&0) {
+                self.bits.pop();
+            }
+        }
+    }
+    pub fn contains(&self, id: usize) -> bool {
+        let (word, bit) = (id / 64, id % 64);
+        word < self.bits.len() && self.bits[word] & (1 << bit) != 0
+    }
+    //true if every component in other is also in here
+    pub fn contains_all(&self, other: &Signature) -> bool {
+        other.bits.iter().enumerate().all(|(i, &word)| {
+            self.bits.get(i).map_or(0, |&w| w) & word == word
+        })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.bits.clear();
+    }
+    //ids of every component in the set, lowest first
+    pub fn iter(&self) -> impl Iterator<Item=usize> + '_ {
+        self.bits.iter().enumerate().flat_map(|(i, &word)| {
+            (0..64).filter(move |bit| word & (1 << bit) != 0).map(move |bit| i * 64 + bit)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn past_128_bits() {
+        let mut s = Signature::new();
+        s.insert(3);
+        s.insert(200);
+        assert!(s.contains(200));
+        assert!(!s.contains(136));
+        assert_eq!(s.iter().collect::<Vec<_>>(), vec![3, 200]);
+        s.remove(200);
+        let mut t = Signature::new();
+        t.insert(3);
+        assert_eq!(s, t);
+        assert!(s.contains_all(&t));
+        t.insert(129);
+        assert!(!s.contains_all(&t));
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use crate::world::{Component, ComponentStorage};
+use crate::signature::Signature;
+
+//one table per entity signature, so entities with the same set of components
+//sit next to each other and queries can skip over whole tables that don't match
+//GameState calls relocate() whenever an entity's signature changes, which moves its row
+pub struct ArchetypeStorage<C> {
+    tables: Vec<Table<C>>,
+    table_ids: HashMap<Signature, usize>,
+    locations: Vec<Option<(usize, usize)>>, //entity index -> (table, row)
+}
+
+struct Table<C> {
+    signature: Signature,
+    entities: Vec<usize>,
+    data: Vec<C>,
+}
+
+impl<C> ArchetypeStorage<C> {
+    fn table_for(&mut self, signature: &Signature) -> usize {
+        if let Some(&t) = self.table_ids.get(signature) {
+            return t;
+        }
+        self.tables.push(Table{signature: signature.clone(), entities: Vec::new(), data: Vec::new()});
+        self.table_ids.insert(signature.clone(), self.tables.len() - 1);
+        self.tables.len() - 1
+    }
+    fn push(&mut self, table: usize, entity: usize, c: C) {
+        let t = &mut self.tables[table];
+        t.entities.push(entity);
+        t.data.push(c);
+        if entity >= self.locations.len() {
+            self.locations.resize(entity + 1, None);
+        }
+        self.locations[entity] = Some((table, t.data.len() - 1));
+    }
+    //swap_remove, then fix up whoever got swapped into the hole
+    fn take(&mut self, entity: usize) -> Option<C> {
+        let (table, row) = self.locations.get_mut(entity)?.take()?;
+        let t = &mut self.tables[table];
+        t.entities.swap_remove(row);
+        let c = t.data.swap_remove(row);
+        if row < t.entities.len() {
+            self.locations[t.entities[row]] = Some((table, row));
+        }
+        Some(c)
+    }
+    //how many entities are in each table, i.e. for debugging fragmentation
+    pub fn table_sizes(&self) -> Vec<(&Signature, usize)> {
+        self.tables.iter().map(|t| (&t.signature, t.entities.len())).collect()
+    }
+}
+
+impl<C> ComponentStorage<C> for ArchetypeStorage<C> where C: Component + 'static + Sized {
+    const ARCHETYPAL: bool = true;
+    fn new() -> ArchetypeStorage<C> {
+        ArchetypeStorage{tables: Vec::new(), table_ids: HashMap::new(), locations: Vec::new()}
+    }
+    //lands in the empty-signature table until relocate() says where it belongs
+    fn insert(&mut self, entity: usize, c: C) {
+        if let Some(existing) = self.get_mut(entity) {
+            *existing = c;
+        } else {
+            let t = self.table_for(&Signature::new());
+            self.push(t, entity, c);
+        }
+    }
+    fn delete(&mut self, entity: usize) {
+        self.take(entity);
+    }
+    fn get(&self, entity: usize) -> Option<&C> {
+        let (table, row) = (*self.locations.get(entity)?)?;
+        Some(&self.tables[table].data[row])
+    }
+    fn get_mut(&mut self, entity: usize) -> Option<&mut C> {
+        let (table, row) = (*self.locations.get(entity)?)?;
+        Some(&mut self.tables[table].data[row])
+    }
+    fn relocate(&mut self, entity: usize, signature: &Signature) {
+        if let Some(Some((table, _))) = self.locations.get(entity) {
+            if self.tables[*table].signature == *signature {
+                return;
+            }
+        }
+        if let Some(c) = self.take(entity) {
+            let t = self.table_for(signature);
+            self.push(t, entity, c);
+        }
+    }
+    fn len(&self) -> usize {
+        self.tables.iter().map(|t| t.entities.len()).sum()
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item=(usize, &C)> + '_> {
+        Box::new(self.tables.iter().flat_map(|t| t.entities.iter().cloned().zip(t.data.iter())))
+    }
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item=(usize, &mut C)> + '_> {
+        Box::new(self.tables.iter_mut().flat_map(|t| t.entities.iter().cloned().zip(t.data.iter_mut())))
+    }
+    fn matching(&self, required: &Signature) -> Vec<usize> {
+        self.tables.iter()
+            .filter(|t| t.signature.contains_all(required))
+            .flat_map(|t| t.entities.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Position(i32);
+    impl Component for Position {
+        type Storage = ArchetypeStorage<Self>;
+    }
+
+    fn signature(ids: &[usize]) -> Signature {
+        let mut s = Signature::new();
+        for &id in ids {
+            s.insert(id);
+        }
+        s
+    }
+
+    #[test]
+    fn moves_between_tables() {
+        let mut s = ArchetypeStorage::<Position>::new();
+        for i in 0..4 {
+            s.insert(i, Position(i as i32));
+            s.relocate(i, &signature(&[0]));
+        }
+        //entities 1 and 3 pick up some other component
+        s.relocate(1, &signature(&[0, 1]));
+        s.relocate(3, &signature(&[0, 1]));
+        assert_eq!(s.matching(&signature(&[0, 1])), vec![1, 3]);
+        let mut all = s.matching(&signature(&[0]));
+        all.sort();
+        assert_eq!(all, vec![0, 1, 2, 3]);
+        //swap_remove kept everyone else findable
+        s.delete(0);
+        assert_eq!(s.get(0), None);
+        assert_eq!(s.get(2), Some(&Position(2)));
+        assert_eq!(s.get(3), Some(&Position(3)));
+        s.insert(3, Position(30));
+        assert_eq!(s.get(3), Some(&Position(30)));
+        assert_eq!(s.len(), 3);
+        assert_eq!(s.iter().count(), 3);
+    }
+}
@@ -19,4 +19,13 @@ impl<C> ComponentStorage<C> for BTreeMap<usize, C> where C: Component + 'static
     fn get_mut(&mut self, entity: usize) -> Option<&mut C> {
         self.get_mut(&entity)
     }
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item=(usize, &C)> + '_> {
+        Box::new(BTreeMap::iter(self).map(|(&i, c)| (i, c)))
+    }
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item=(usize, &mut C)> + '_> {
+        Box::new(BTreeMap::iter_mut(self).map(|(&i, c)| (i, c)))
+    }
 }
\ No newline at end of file
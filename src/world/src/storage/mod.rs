@@ -3,4 +3,10 @@ pub use self::btreemap::BTreeMapStorage;
 pub mod hashmap;
 pub use self::hashmap::HashMapStorage;
 pub mod vec;
-pub use self::vec::VecStorage;
\ No newline at end of file
+pub use self::vec::VecStorage;
+pub mod archetype;
+pub use self::archetype::ArchetypeStorage;
+pub mod sparse_set;
+pub use self::sparse_set::SparseSetStorage;
+pub mod null;
+pub use self::null::NullStorage;
\ No newline at end of file
@@ -0,0 +1,64 @@
+use crate::world::{Component, ComponentStorage};
+
+//for zero-sized flag components like Player or Deleted: all that's stored is one bit per entity
+//(values is a Vec of zero-sized things, so it never actually allocates;
+//it's just there so there's something to hand out references to)
+pub struct NullStorage<C> {
+    bits: Vec<u64>,
+    values: Vec<C>,
+    count: usize,
+}
+
+fn contains(bits: &[u64], entity: usize) -> bool {
+    bits.get(entity / 64).is_some_and(|word| word & (1 << (entity % 64)) != 0)
+}
+
+impl<C> ComponentStorage<C> for NullStorage<C> where C: Component + 'static + Sized {
+    fn new() -> NullStorage<C> {
+        assert!(std::mem::size_of::<C>() == 0, "NullStorage is only for zero-sized components");
+        NullStorage{bits: Vec::new(), values: Vec::new(), count: 0}
+    }
+    fn insert(&mut self, entity: usize, c: C) {
+        if entity / 64 >= self.bits.len() {
+            self.bits.resize(entity / 64 + 1, 0);
+        }
+        if entity >= self.values.len() {
+            self.values.resize(entity + 1, c);
+        }
+        if !contains(&self.bits, entity) {
+            self.bits[entity / 64] |= 1 << (entity % 64);
+            self.count += 1;
+        }
+    }
+    fn delete(&mut self, entity: usize) {
+        if contains(&self.bits, entity) {
+            self.bits[entity / 64] &= !(1 << (entity % 64));
+            self.count -= 1;
+        }
+    }
+    fn get(&self, entity: usize) -> Option<&C> {
+        if contains(&self.bits, entity) {
+            self.values.get(entity)
+        } else {
+            None
+        }
+    }
+    fn get_mut(&mut self, entity: usize) -> Option<&mut C> {
+        if contains(&self.bits, entity) {
+            self.values.get_mut(entity)
+        } else {
+            None
+        }
+    }
+    fn len(&self) -> usize {
+        self.count
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item=(usize, &C)> + '_> {
+        let bits = &self.bits;
+        Box::new(self.values.iter().enumerate().filter(move |&(i, _)| contains(bits, i)))
+    }
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item=(usize, &mut C)> + '_> {
+        let bits = &self.bits;
+        Box::new(self.values.iter_mut().enumerate().filter(move |(i, _)| contains(bits, *i)))
+    }
+}
@@ -0,0 +1,84 @@
+use crate::world::{Component, ComponentStorage};
+
+//components packed into a dense Vec, with a sparse Vec of indices into it
+//insert/delete are O(1) (delete swaps the last one into the hole),
+//and rarely-used components only cost a usize per entity instead of a whole Option<C>
+pub struct SparseSetStorage<C> {
+    dense: Vec<C>,
+    entities: Vec<usize>, //dense index -> entity
+    sparse: Vec<Option<usize>>, //entity -> dense index
+}
+
+impl<C> ComponentStorage<C> for SparseSetStorage<C> where C: Component + 'static + Sized {
+    fn new() -> SparseSetStorage<C> {
+        SparseSetStorage{dense: Vec::new(), entities: Vec::new(), sparse: Vec::new()}
+    }
+    fn insert(&mut self, entity: usize, c: C) {
+        if let Some(existing) = self.get_mut(entity) {
+            *existing = c;
+            return;
+        }
+        if entity >= self.sparse.len() {
+            self.sparse.resize(entity + 1, None);
+        }
+        self.sparse[entity] = Some(self.dense.len());
+        self.dense.push(c);
+        self.entities.push(entity);
+    }
+    fn delete(&mut self, entity: usize) {
+        if let Some(i) = self.sparse.get_mut(entity).and_then(|i| i.take()) {
+            self.dense.swap_remove(i);
+            self.entities.swap_remove(i);
+            if i < self.entities.len() {
+                self.sparse[self.entities[i]] = Some(i);
+            }
+        }
+    }
+    fn get(&self, entity: usize) -> Option<&C> {
+        let i = (*self.sparse.get(entity)?)?;
+        Some(&self.dense[i])
+    }
+    fn get_mut(&mut self, entity: usize) -> Option<&mut C> {
+        let i = (*self.sparse.get(entity)?)?;
+        Some(&mut self.dense[i])
+    }
+    fn len(&self) -> usize {
+        self.dense.len()
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item=(usize, &C)> + '_> {
+        Box::new(self.entities.iter().cloned().zip(self.dense.iter()))
+    }
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item=(usize, &mut C)> + '_> {
+        Box::new(self.entities.iter().cloned().zip(self.dense.iter_mut()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Rare(usize);
+    impl Component for Rare {
+        type Storage = SparseSetStorage<Self>;
+    }
+
+    #[test]
+    fn delete_keeps_the_rest() {
+        let mut s = SparseSetStorage::<Rare>::new();
+        for i in &[3, 70, 5] {
+            s.insert(*i, Rare(*i));
+        }
+        s.delete(3);
+        s.delete(3);
+        assert_eq!(s.get(3), None);
+        assert_eq!(s.get(70), Some(&Rare(70)));
+        assert_eq!(s.get(5), Some(&Rare(5)));
+        s.insert(5, Rare(50));
+        assert_eq!(s.get(5), Some(&Rare(50)));
+        assert_eq!(s.get(1000), None);
+        let mut all: Vec<_> = s.iter().map(|(i, c)| (i, c.0)).collect();
+        all.sort();
+        assert_eq!(all, vec![(5, 50), (70, 70)]);
+    }
+}
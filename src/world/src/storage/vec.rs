@@ -1,38 +1,69 @@
 use crate::world::{Component, ComponentStorage};
 
-pub type VecStorage<C> = Vec<Option<C>>;
+//a slot for every entity, whether it has one or not
+//keeps count of the full ones as it goes, since queries ask for len() every time to pick the smallest storage to drive from
+pub struct VecStorage<C> {
+    slots: Vec<Option<C>>,
+    count: usize,
+}
 
-impl<C> ComponentStorage<C> for Vec<Option<C>> where C: Component + 'static + Sized {
-    fn new() -> Vec<Option<C>> {
-        Vec::new()
+impl<C> ComponentStorage<C> for VecStorage<C> where C: Component + 'static + Sized {
+    fn new() -> VecStorage<C> {
+        VecStorage{slots: Vec::new(), count: 0}
     }
     fn insert(&mut self, entity: usize, c: C) {
-        if entity >= self.len() {
-            self.reserve(entity - self.len() + 1);
-            for _ in 0..=entity - self.len() {
-                self.push(None);
-            }
+        if entity >= self.slots.len() {
+            self.slots.resize_with(entity + 1, || None);
+        }
+        if self.slots[entity].replace(c).is_none() {
+            self.count += 1;
         }
-        self[entity] = Some(c);
     }
     fn delete(&mut self, entity: usize) {
-        if entity < self.len() {
-            self[entity] = None;
+        if self.slots.get_mut(entity).and_then(|c| c.take()).is_some() {
+            self.count -= 1;
         }
     }
     fn get(&self, entity: usize) -> Option<&C> {
-        if entity >= self.len() {
-            None
-        } else {
-            self[entity].as_ref()
-        }
+        self.slots.get(entity)?.as_ref()
     }
     fn get_mut(&mut self, entity: usize) -> Option<&mut C> {
-        if entity >= self.len() {
-            None
-        } else {
-            self[entity].as_mut()
-        }
+        self.slots.get_mut(entity)?.as_mut()
+    }
+    fn len(&self) -> usize {
+        self.count
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item=(usize, &C)> + '_> {
+        Box::new(self.slots.iter().enumerate().filter_map(|(i, c)| c.as_ref().map(|c| (i, c))))
+    }
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item=(usize, &mut C)> + '_> {
+        Box::new(self.slots.iter_mut().enumerate().filter_map(|(i, c)| c.as_mut().map(|c| (i, c))))
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Hp(i32);
+    impl Component for Hp {
+        type Storage = VecStorage<Self>;
+    }
+
+    #[test]
+    fn counts_full_slots() {
+        let mut s = VecStorage::<Hp>::new();
+        s.insert(4, Hp(1));
+        s.insert(4, Hp(2));
+        s.insert(0, Hp(3));
+        assert_eq!(s.len(), 2);
+        //deleting twice, or past the end, doesn't count
+        s.delete(4);
+        s.delete(4);
+        s.delete(100);
+        assert_eq!(s.len(), 1);
+        assert_eq!(s.get(4), None);
+        assert_eq!(s.iter().map(|(i, c)| (i, c.0)).collect::<Vec<_>>(), vec![(0, 3)]);
+    }
+}
@@ -0,0 +1,201 @@
+use std::cell::Cell;
+use std::hash::Hash;
+use std::ops::Deref;
+use crate::world::{Component, ComponentStorage, Entity};
+use crate::signature::Signature;
+use crate::index::{AnyIndex, Index};
+
+//a component's storage plus when each entry was added/last changed, and what's been deleted lately
+//this is what actually sits in GameState's RwLocks; it derefs to the storage for reading,
+//but anything that changes a component has to come through here so the ticks stay right
+pub struct Column<C: Component> {
+    storage: C::Storage,
+    ticks: Vec<Ticks>, //indexed by entity index, always long enough for anything that's in storage
+    removed: Vec<(Entity, u64)>,
+    indices: Vec<Box<dyn AnyIndex<C>>>, //see index.rs
+}
+
+#[derive(Clone, Copy, Default)]
+struct Ticks {
+    added: u64,
+    changed: u64,
+}
+
+impl<C: Component> Column<C> {
+    pub(crate) fn new() -> Column<C> {
+        Column{storage: C::Storage::new(), ticks: Vec::new(), removed: Vec::new(), indices: Vec::new()}
+    }
+    pub(crate) fn insert(&mut self, index: usize, c: C, tick: u64) {
+        if index >= self.ticks.len() {
+            self.ticks.resize(index + 1, Ticks::default());
+        }
+        if self.storage.get(index).is_none() {
+            self.ticks[index].added = tick;
+        }
+        self.ticks[index].changed = tick;
+        for i in &mut self.indices {
+            i.inserted(index, &c);
+        }
+        self.storage.insert(index, c);
+    }
+    pub(crate) fn delete(&mut self, entity: Entity, tick: u64) {
+        if self.storage.get(entity.id()).is_some() {
+            for i in &mut self.indices {
+                i.deleted(entity.id());
+            }
+            self.storage.delete(entity.id());
+            self.removed.push((entity, tick));
+        }
+    }
+    pub(crate) fn relocate(&mut self, index: usize, signature: &Signature) {
+        self.storage.relocate(index, signature);
+    }
+    //handing out a &mut counts as changing it, whether or not anything actually gets written
+    pub(crate) fn get_mut_tracked(&mut self, index: usize, tick: u64) -> Option<&mut C> {
+        let c = self.storage.get_mut(index)?;
+        self.ticks[index].changed = tick;
+        for i in &mut self.indices {
+            i.touched(index);
+        }
+        Some(c)
+    }
+    pub(crate) fn iter_mut_tracked(&mut self, tick: u64) -> impl Iterator<Item=(usize, &mut C)> {
+        for i in &mut self.indices {
+            i.touched_all();
+        }
+        let ticks = &mut self.ticks;
+        self.storage.iter_mut().map(move |(i, c)| {
+            ticks[i].changed = tick;
+            (i, c)
+        })
+    }
+    pub(crate) fn added_since(&self, index: usize, tick: u64) -> bool {
+        self.storage.get(index).is_some() && self.ticks[index].added > tick
+    }
+    pub(crate) fn changed_since(&self, index: usize, tick: u64) -> bool {
+        self.storage.get(index).is_some() && self.ticks[index].changed > tick
+    }
+    pub(crate) fn removed_since(&self, tick: u64) -> impl Iterator<Item=Entity> + '_ {
+        self.removed.iter().filter(move |&&(_, t)| t > tick).map(|&(e, _)| e)
+    }
+    pub(crate) fn forget_removed(&mut self, up_to: u64) {
+        self.removed.retain(|&(_, t)| t > up_to);
+    }
+    //only one per key type; adding another replaces it
+    pub(crate) fn add_index<K: Eq + Hash + Clone + Send + Sync + 'static>(&mut self, key: fn(&C) -> K) {
+        self.indices.retain(|i| !i.as_any().is::<Index<C, K>>());
+        self.indices.push(Box::new(Index::new(key, &self.storage)));
+    }
+    pub(crate) fn lookup<K: Eq + Hash + Clone + Send + Sync + 'static>(&self, key: &K) -> Option<Vec<usize>> {
+        let index = self.indices.iter().find_map(|i| i.as_any().downcast_ref::<Index<C, K>>())?;
+        Some(index.get(&self.storage, key))
+    }
+}
+
+impl<C: Component> Deref for Column<C> {
+    type Target = C::Storage;
+    fn deref(&self) -> &C::Storage {
+        &self.storage
+    }
+}
+
+thread_local! {
+    //(last_run, this_run) for whichever system is running on this thread, see GameState::run_system
+    static SYSTEM_TICKS: Cell<Option<(u64, u64)>> = const { Cell::new(None) };
+}
+
+pub(crate) fn system_ticks() -> Option<(u64, u64)> {
+    SYSTEM_TICKS.with(|t| t.get())
+}
+pub(crate) fn set_system_ticks(ticks: Option<(u64, u64)>) -> Option<(u64, u64)> {
+    SYSTEM_TICKS.with(|t| t.replace(ticks))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use crate::world::{GameState, Component, Entity};
+    use crate::query::{Added, Changed};
+    use crate::schedule::{Schedule, Stage};
+    use crate::storage::{VecStorage, SparseSetStorage};
+
+    #[derive(Clone)]
+    struct Position(i32);
+    impl Component for Position {
+        type Storage = VecStorage<Self>;
+    }
+    #[derive(Clone)]
+    struct Stackable;
+    impl Component for Stackable {
+        type Storage = SparseSetStorage<Self>;
+    }
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Seen {
+        added: Vec<Entity>,
+        changed: Vec<Entity>,
+        removed: Vec<Entity>,
+    }
+
+    #[test]
+    fn added_changed_removed() {
+        let mut w = GameState::new();
+        w.register_component::<Position>();
+        w.register_component::<Stackable>();
+        let seen = RefCell::new(Seen::default());
+        let mut schedule = Schedule::new();
+        schedule.add_local_system("watch", Stage::Update, |w| {
+            let mut seen = seen.borrow_mut();
+            *seen = Seen::default();
+            w.query::<(Added<Stackable>,)>().for_each_entity(|e, _| seen.added.push(e));
+            w.query::<(Changed<Position>,)>().for_each_entity(|e, _| seen.changed.push(e));
+            seen.removed.extend(w.removed::<Stackable>());
+        });
+        let a = w.create_entity();
+        let b = w.create_entity();
+        w.insert(a, Position(0));
+        w.insert(a, Stackable);
+        w.insert(b, Position(0));
+        schedule.run(&mut w);
+        assert_eq!(*seen.borrow(), Seen{added: vec![a], changed: vec![a, b], removed: vec![]});
+        schedule.run(&mut w);
+        assert_eq!(*seen.borrow(), Seen::default());
+        w.update(a, |p: &mut Position| p.0 += 1);
+        w.delete::<Stackable>(a);
+        schedule.run(&mut w);
+        assert_eq!(*seen.borrow(), Seen{added: vec![], changed: vec![a], removed: vec![a]});
+        w.delete_entity(b);
+        schedule.run(&mut w);
+        schedule.run(&mut w);
+        assert_eq!(*seen.borrow(), Seen::default());
+    }
+
+    #[test]
+    fn only_matches_count_as_changed() {
+        let mut w = GameState::new();
+        w.register_component::<Position>();
+        w.register_component::<Stackable>();
+        let changed = RefCell::new(Vec::new());
+        let mut schedule = Schedule::new();
+        schedule.add_local_system("move", Stage::Update, |w| {
+            w.query::<(&mut Position, &Stackable)>().for_each(|(pos, _)| pos.0 += 1);
+        });
+        schedule.add_local_system("watch", Stage::Update, |w| {
+            let mut changed = changed.borrow_mut();
+            changed.clear();
+            w.query::<(Changed<Position>,)>().for_each_entity(|e, _| changed.push(e));
+        }).after("move");
+        let a = w.create_entity();
+        let b = w.create_entity();
+        w.insert(a, Position(0));
+        w.insert(a, Stackable);
+        w.insert(b, Position(0));
+        //as many Stackables as Positions, so the query goes through the Positions
+        let c = w.create_entity();
+        w.insert(c, Stackable);
+        schedule.run(&mut w);
+        schedule.run(&mut w);
+        //b's Position would have been handed out before its missing Stackable turned it away
+        assert_eq!(*changed.borrow(), vec![a]);
+    }
+}
@@ -1,15 +1,24 @@
-use std::collections::HashMap;
-use std::sync::RwLock;
-use std::cell::RefCell;
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::{RwLock, Mutex};
+use std::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
 use std::any::{TypeId, Any}; //for a little bit of dynamic typing
+use crate::query::{Query, QueryBorrow};
+use crate::commands::{Command, Commands};
+use crate::signature::Signature;
+use crate::borrow::{BorrowTracker, StorageRef, StorageMut};
+use crate::error::Error;
+use crate::tracking::{self, Column};
+use crate::events::{Events, EventCursor};
+use crate::hooks::{Hook, Hooks};
+use crate::hierarchy::{self, Parent, Children};
+use crate::names::{self, Name};
+use crate::bundle::EntityBuilder;
 
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
 pub struct Entity {
-    index: usize,
-    generation: usize,
-    hash: i128,
+    pub(crate) index: usize,
+    pub(crate) generation: usize,
 }
 impl Entity {
     pub fn id(&self) -> usize {
@@ -18,154 +27,584 @@ impl Entity {
 }
 
 pub trait ComponentStorage<C: Component> {
+    //storages that lay things out by the entity's whole signature (i.e. ArchetypeStorage) set this,
+    //so GameState knows to call relocate() whenever an entity gains or loses a component
+    const ARCHETYPAL: bool = false;
     fn new() -> Self where Self: Sized;
     fn insert(&mut self, entity: usize, c: C);
     fn delete(&mut self, entity: usize);
     fn get(&self, entity: usize) -> Option<&C>;
     fn get_mut(&mut self, entity: usize) -> Option<&mut C>;
+    //how many entities actually have one of these
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    //only the occupied slots, as (entity index, component)
+    fn iter(&self) -> Box<dyn Iterator<Item=(usize, &C)> + '_>;
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item=(usize, &mut C)> + '_>;
+    fn relocate(&mut self, _entity: usize, _signature: &Signature) {}
+    //which entities in here could have all of required, in the order they're laid out
+    //archetypal storages can skip whole tables; everyone else just lists what they have
+    fn matching(&self, _required: &Signature) -> Vec<usize> {
+        self.iter().map(|(i, _)| i).collect()
+    }
 }
 
-pub trait Component: 'static + Sized + Clone {
-    type Storage: ComponentStorage<Self>;
+pub trait Component: 'static + Sized + Clone + Send + Sync {
+    type Storage: ComponentStorage<Self> + Send + Sync;
 }
 
-pub trait Resource: 'static + Sized + Clone {}
+//anything there's only one of, i.e. frame time or input state
+pub trait Resource: 'static + Sized + Send + Sync {}
 
-use crate::storage::BTreeMapStorage;
+use crate::storage::NullStorage;
 #[derive(Clone)]
 pub struct Deleted;
 impl Component for Deleted {
-    type Storage = BTreeMapStorage<Self>;
+    type Storage = NullStorage<Self>;
+}
+
+//what register_component knows about each component type
+//its id is its bit in a Signature
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ComponentInfo {
+    pub id: usize,
+    pub type_id: TypeId,
+    pub name: &'static str,
+}
+
+//just the entity slots, for snapshots: every slot's generation, and which ones are free
+//see GameState::entity_table/restore_entity_table
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EntityTable {
+    pub(crate) generations: Vec<usize>,
+    pub(crate) free: Vec<usize>,
 }
 
 //za warudo
 pub struct GameState {
-    entities: Vec<Entity>, //uses RefCell and not RwLock because shouldn't be accessed outside here
-    new_entities: RefCell<Vec<Entity>>,
-    updated_entities: RefCell<Vec<Entity>>,
-    entities_size: AtomicUsize, //this is so we can iterate without having to access entities/new_entities directly
-    hashes: HashMap<TypeId, i128>,
-    hash_base: i128,
-    world: HashMap<TypeId, Box<Any>>,
-    resources: HashMap<TypeId, Box<Any>>,
+    entities: Vec<Entity>, //only changes in update_entities, which has the world to itself
+    new_entities: Mutex<Vec<Entity>>, //Mutex not RefCell so systems on other threads can create entities too
+    signatures: Mutex<Vec<Signature>>, //indexed by entity index, kept up to date by insert/delete
+    pub(crate) entities_size: AtomicUsize, //this is so we can iterate without having to access entities/new_entities directly
+    free_entities: Mutex<Vec<usize>>, //slots freed by sweep_delete, handed back out by create_entity
+    component_ids: HashMap<TypeId, usize>,
+    components: Vec<ComponentInfo>, //indexed by component id
+    world: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    deleters: Vec<fn(&GameState, usize)>, //indexed by component id, so sweep_delete can clear storages without knowing their types
+    relocators: Vec<fn(&GameState, usize, &Signature)>, //same deal, for archetypal storages
+    forgetters: Vec<fn(&GameState, u64)>, //and for trimming the removed::<C>() logs
+    remove_hooks: Vec<fn(&GameState, Entity)>, //and for firing on_remove hooks when sweep_delete takes a whole entity apart
+    archetypal: Signature, //ids of components whose storage wants relocate() calls
+    resources: HashMap<TypeId, Box<dyn Any + Send + Sync>>, //each one's in its own RwLock, same as the storages
+    event_updaters: Vec<fn(&GameState)>, //one per add_event, for flipping the buffers once a frame
+    hooks: HashMap<TypeId, Box<dyn Any + Send + Sync>>, //Hooks<C>, only for components that have any
+    pub(crate) commands: Mutex<Vec<Command>>, //queued up by Commands, run in update_entities
+    borrows: BorrowTracker, //who's got which storage locked, in debug builds
+    change_tick: AtomicU64, //goes up every time a system starts, and on every change made outside of one
+}
+
+fn delete_component<C: Component>(w: &GameState, index: usize) {
+    let tick = w.this_run();
+    w.lock_write::<C>().delete(w.entity_at(index), tick);
+}
+fn relocate_component<C: Component>(w: &GameState, index: usize, signature: &Signature) {
+    w.lock_write::<C>().relocate(index, signature);
+}
+fn forget_removed<C: Component>(w: &GameState, up_to: u64) {
+    w.lock_write::<C>().forget_removed(up_to);
+}
+fn update_events<T: Send + Sync + 'static>(w: &GameState) {
+    w.resource_mut::<Events<T>>().update();
 }
 
 impl GameState {
     pub fn new() -> GameState {
         let mut w = GameState{
             entities: Vec::new(), 
-            new_entities: RefCell::new(Vec::new()), 
-            updated_entities: RefCell::new(Vec::new()), 
+            new_entities: Mutex::new(Vec::new()), 
+            signatures: Mutex::new(Vec::new()),
             entities_size: AtomicUsize::new(0),
-            hashes: HashMap::new(), 
-            hash_base: 1, 
+            free_entities: Mutex::new(Vec::new()),
+            component_ids: HashMap::new(),
+            components: Vec::new(),
             world: HashMap::new(), 
-            resources: HashMap::new()
+            deleters: Vec::new(),
+            relocators: Vec::new(),
+            forgetters: Vec::new(),
+            remove_hooks: Vec::new(),
+            archetypal: Signature::new(),
+            resources: HashMap::new(),
+            event_updaters: Vec::new(),
+            hooks: HashMap::new(),
+            commands: Mutex::new(Vec::new()),
+            borrows: BorrowTracker::default(),
+            change_tick: AtomicU64::new(1),
         };
         w.register_component::<Deleted>();
+        hierarchy::init(&mut w);
+        names::init(&mut w);
         w
     }
+}
 
+impl Default for GameState {
+    fn default() -> GameState {
+        GameState::new()
+    }
+}
+
+impl GameState {
     pub fn set_resource<R: Resource>(&mut self, resource: R) {
-        self.resources.insert(TypeId::of::<R>(), Box::new(resource));
+        self.resources.insert(TypeId::of::<R>(), Box::new(RwLock::new(resource)));
+    }
+    pub fn remove_resource<R: Resource>(&mut self) -> Option<R> {
+        let resource = self.resources.remove(&TypeId::of::<R>())?;
+        Some(resource.downcast::<RwLock<R>>().unwrap().into_inner().unwrap())
+    }
+    pub fn has_resource<R: Resource>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<R>())
+    }
+    //a copy, for when it's not worth holding onto the lock
+    #[track_caller]
+    pub fn get_resource<R: Resource + Clone>(&self) -> Option<R> {
+        self.try_resource::<R>().ok().map(|r| r.clone())
+    }
+    //borrow it in place, i.e. *w.resource_mut::<Score>() += 10;
+    //same rules as component storages, so don't hang onto these across a call that wants the same resource
+    #[track_caller]
+    pub fn resource<R: Resource>(&self) -> StorageRef<'_, R> {
+        match self.try_resource::<R>() {
+            Ok(r) => r,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    #[track_caller]
+    pub fn resource_mut<R: Resource>(&self) -> StorageMut<'_, R> {
+        match self.try_resource_mut::<R>() {
+            Ok(r) => r,
+            Err(e) => panic!("{}", e),
+        }
     }
-    pub fn get_resource<R: Resource>(&self) -> Option<R> {
-        self.resources.get(&TypeId::of::<R>()).map(|r| r.downcast_ref::<R>().unwrap().clone())
+    #[track_caller]
+    pub fn try_resource<R: Resource>(&self) -> Result<StorageRef<'_, R>, Error> {
+        let resource = self.get_resource_lock::<R>()?;
+        let borrow = self.borrows.acquire(TypeId::of::<R>(), std::any::type_name::<R>(), false)?;
+        Ok(StorageRef::new(resource.read().unwrap(), borrow))
+    }
+    #[track_caller]
+    pub fn try_resource_mut<R: Resource>(&self) -> Result<StorageMut<'_, R>, Error> {
+        let resource = self.get_resource_lock::<R>()?;
+        let borrow = self.borrows.acquire(TypeId::of::<R>(), std::any::type_name::<R>(), true)?;
+        Ok(StorageMut::new(resource.write().unwrap(), borrow))
+    }
+    fn get_resource_lock<R: Resource>(&self) -> Result<&RwLock<R>, Error> {
+        match self.resources.get(&TypeId::of::<R>()) {
+            Some(resource) => Ok(resource.downcast_ref::<RwLock<R>>().unwrap()),
+            None => Err(Error::MissingResource(std::any::type_name::<R>())),
+        }
+    }
+
+    //events are just resources, plus getting their buffers flipped by update_events()
+    //i.e. w.send(Died{entity}); ... for died in w.read_events::<Died>(&mut cursor) { ... }
+    pub fn add_event<T: Send + Sync + 'static>(&mut self) {
+        if !self.has_resource::<Events<T>>() {
+            self.set_resource(Events::<T>::new());
+            self.event_updaters.push(update_events::<T>);
+        }
+    }
+    #[track_caller]
+    pub fn send<T: Send + Sync + 'static>(&self, event: T) {
+        self.resource_mut::<Events<T>>().send(event);
+    }
+    //copies, so nothing's locked while they're being handled (and handling them can send more)
+    #[track_caller]
+    pub fn read_events<T: Send + Sync + Clone + 'static>(&self, cursor: &mut EventCursor<T>) -> Vec<T> {
+        cursor.read(&self.resource::<Events<T>>()).cloned().collect()
+    }
+    //drops everything sent before the previous call; Schedule::run does this at the end of every frame
+    pub fn update_events(&self) {
+        for update in &self.event_updaters {
+            update(self);
+        }
+    }
+    //drops everything that's buffered, i.e. events sent in frames that have just been rolled back
+    pub fn clear_events(&self) {
+        self.update_events();
+        self.update_events();
     }
 
     pub fn register_component<C: Component>(&mut self) {
         //wrap up Storage in a RWLock for concurrency :3
-        self.world.entry(TypeId::of::<C>()).or_insert(Box::new(RwLock::new(C::Storage::new())));
-        if self.hashes.get(&TypeId::of::<C>()).is_none() {
-            self.hashes.insert(TypeId::of::<C>(), self.hash_base);
-            self.hash_base <<= 1;
+        self.world.entry(TypeId::of::<C>()).or_insert(Box::new(RwLock::new(Column::<C>::new())));
+        if !self.component_ids.contains_key(&TypeId::of::<C>()) {
+            let id = self.components.len();
+            self.component_ids.insert(TypeId::of::<C>(), id);
+            self.components.push(ComponentInfo{id, type_id: TypeId::of::<C>(), name: std::any::type_name::<C>()});
+            self.deleters.push(delete_component::<C>);
+            self.relocators.push(relocate_component::<C>);
+            self.forgetters.push(forget_removed::<C>);
+            self.remove_hooks.push(GameState::fire_on_remove::<C>);
+            if <C::Storage as ComponentStorage<C>>::ARCHETYPAL {
+                self.archetypal.insert(id);
+            }
+        }
+    }
+    #[track_caller]
+    pub fn component_id<C: Component>(&self) -> usize {
+        match self.component_ids.get(&TypeId::of::<C>()) {
+            Some(&id) => id,
+            None => panic!("{}", Error::UnregisteredComponent(std::any::type_name::<C>())),
+        }
+    }
+    //moves the entity's rows in any archetypal storages to match its new signature
+    fn signature_changed(&self, index: usize) {
+        if self.archetypal.is_empty() {
+            return;
+        }
+        let signature = self.signatures.lock().unwrap()[index].clone();
+        for id in signature.iter().filter(|&id| self.archetypal.contains(id)) {
+            (self.relocators[id])(self, index, &signature);
         }
     }
     pub fn create_entity(&self) -> Entity {
-        let e = Entity{index: self.entities_size.load(Ordering::SeqCst), generation: 0, hash: 0};
-        self.new_entities.borrow_mut().push(e);
+        //sweep_delete already bumped the generation of freed slots, so they can be handed out as-is
+        if let Some(index) = self.free_entities.lock().unwrap().pop() {
+            return self.entities[index];
+        }
+        //holding onto new_entities the whole time so two threads can't both take the same index
+        let mut new_entities = self.new_entities.lock().unwrap();
+        let e = Entity{index: self.entities.len() + new_entities.len(), generation: 0};
+        new_entities.push(e);
+        self.signatures.lock().unwrap().push(Signature::new());
         self.entities_size.fetch_add(1, Ordering::SeqCst);
         e
     }
-    fn intern(&self, entity: Entity) -> Entity {
-        if entity.index > self.entities.len() {
+    fn intern(&self, index: usize) -> Option<Entity> {
+        if index >= self.entities.len() {
             //it hasn't been updated yet; still in new_entities
-            self.new_entities.borrow()[entity.index - self.entities.len()]
+            self.new_entities.lock().unwrap().get(index - self.entities.len()).cloned()
         } else {
-            self.entities[entity.index]
+            Some(self.entities[index])
         }
     }
+    //the current handle for a slot, i.e. with the right generation
+    pub(crate) fn entity_at(&self, index: usize) -> Entity {
+        self.intern(index).unwrap()
+    }
+    //false for handles whose slot has since been swept (and maybe reused)
+    pub(crate) fn is_current(&self, entity: Entity) -> bool {
+        self.intern(entity.index).is_some_and(|e| e.generation == entity.generation)
+    }
+    //for putting a whole entity together in one go, see bundle.rs
+    pub fn spawn(&self) -> EntityBuilder<'_> {
+        EntityBuilder::new(self, self.create_entity())
+    }
+    //same thing for an entity that's already there, i.e. for loading a bunch of components into it at once
+    pub fn build(&self, entity: Entity) -> EntityBuilder<'_> {
+        EntityBuilder::new(self, entity)
+    }
+    //what EntityBuilder does once it's done
+    //merged, not replaced, since the entity could already have had things (or had them inserted the normal way in the meantime)
+    pub(crate) fn spawned(&self, entity: Entity, signature: Signature) {
+        if !self.is_current(entity) {
+            return;
+        }
+        {
+            let existing = &mut self.signatures.lock().unwrap()[entity.index];
+            for id in signature.iter() {
+                existing.insert(id);
+            }
+        }
+        self.signature_changed(entity.index);
+    }
+    //everything that's alive right now, oldest slots first
+    pub fn entities(&self) -> Vec<Entity> {
+        //freed slots already have their next generation, so they'd look alive otherwise
+        let free = self.free_entities.lock().unwrap().clone();
+        (0..self.entities_size.load(Ordering::SeqCst)).filter(|i| !free.contains(i)).map(|i| self.entity_at(i)).filter(|&e| self.is_alive(e)).collect()
+    }
+    //what entity_table/restore_entity_table are for: taking a world back to an earlier frame, see homemade's snapshot.rs
+    //take these between frames, when nothing's waiting to be deleted; anything flagged Deleted is left out
+    pub fn entity_table(&self) -> EntityTable {
+        let generations = (0..self.entities_size.load(Ordering::SeqCst)).map(|i| self.entity_at(i).generation).collect();
+        EntityTable{generations, free: self.free_entities.lock().unwrap().clone()}
+    }
+    //puts the slots back the way they were in table, and takes every component off every entity, without running any hooks
+    //so it's ready for them to be put back with insert_without_hooks; queued up commands are dropped, they belong to what's being undone
+    pub fn restore_entity_table(&mut self, table: &EntityTable) {
+        self.commands.get_mut().unwrap().clear();
+        self.entities.append(self.new_entities.get_mut().unwrap());
+        for i in 0..self.entities.len() {
+            let signature = std::mem::take(&mut self.signatures.get_mut().unwrap()[i]);
+            for id in signature.iter() {
+                (self.deleters[id])(self, i);
+            }
+        }
+        self.entities = table.generations.iter().enumerate().map(|(index, &generation)| Entity{index, generation}).collect();
+        *self.signatures.get_mut().unwrap() = vec![Signature::new(); self.entities.len()];
+        self.entities_size.store(self.entities.len(), Ordering::SeqCst);
+        *self.free_entities.get_mut().unwrap() = table.free.clone();
+    }
     pub fn update_entities(&mut self) {
         /*println!("cur: {:?}", self.entities);
-        println!("new: {:?}", self.new_entities);
-        println!("upd: {:?}", self.updated_entities);*/
-        for e in self.new_entities.borrow_mut().drain(..) {
-            self.entities.push(e);
-        }
-        for e in self.updated_entities.borrow_mut().drain(..) {
-            self.entities[e.index] = e;
+        println!("new: {:?}", self.new_entities);*/
+        //commands can queue up more commands, so keep going until there's none left
+        loop {
+            let commands = std::mem::take(self.commands.get_mut().unwrap());
+            if commands.is_empty() {
+                break;
+            }
+            for command in commands {
+                command(self);
+            }
         }
+        self.sweep_delete();
     }
+    //takes its children (and theirs) with it
+    //flagged before its children, so a hierarchy that somehow loops back round doesn't go on forever
+    #[track_caller]
     pub fn delete_entity(&self, entity: Entity) {
+        let children = self.clone::<Children>(entity);
         self.insert(entity, Deleted);
+        for &child in children.iter().flat_map(|c| c.iter()).filter(|&&child| !self.is_deleted(child)) {
+            self.delete_entity(child);
+        }
     }
+    #[track_caller]
     pub fn is_alive(&self, entity: Entity) -> bool {
-        self.lock_read::<Deleted>().get(entity.index).is_none()
+        self.is_current(entity) && self.lock_read::<Deleted>().get(entity.index).is_none()
     }
+    #[track_caller]
     pub fn is_deleted(&self, entity: Entity) -> bool {
-        self.lock_read::<Deleted>().get(entity.index).is_some()
+        !self.is_current(entity) || self.lock_read::<Deleted>().get(entity.index).is_some()
+    }
+    //the set of components an entity has; entities with equal signatures are the same 'type'
+    pub fn type_of(&self, entity: Entity) -> Signature {
+        if self.is_current(entity) {
+            self.signatures.lock().unwrap()[entity.index].clone()
+        } else {
+            Signature::new()
+        }
+    }
+    //same thing but readable, i.e. for debugging
+    pub fn components_of(&self, entity: Entity) -> Vec<ComponentInfo> {
+        self.type_of(entity).iter().map(|id| self.components[id]).collect()
+    }
+    #[track_caller]
+    pub fn has_component<C: Component>(&self, entity: Entity) -> bool {
+        self.type_of(entity).contains(self.component_id::<C>())
+    }
+
+    //hierarchy, see hierarchy.rs; same as inserting/deleting Parent yourself, except set_parent checks for cycles first
+    #[track_caller]
+    pub fn set_parent(&self, child: Entity, parent: Entity) {
+        match self.try_set_parent(child, parent) {
+            Ok(()) | Err(Error::StaleEntity(_)) => (),
+            Err(e) => panic!("{}", e),
+        }
+    }
+    #[track_caller]
+    pub fn try_set_parent(&self, child: Entity, parent: Entity) -> Result<(), Error> {
+        if self.is_ancestor(child, parent) {
+            return Err(Error::ParentCycle(child));
+        }
+        self.try_insert(child, Parent(parent))
+    }
+    #[track_caller]
+    pub fn remove_parent(&self, child: Entity) {
+        self.delete::<Parent>(child);
+    }
+    //whether ancestor is entity, or its parent, or its parent's parent, and so on
+    #[track_caller]
+    pub fn is_ancestor(&self, ancestor: Entity, entity: Entity) -> bool {
+        let mut seen = HashSet::new();
+        let mut next = Some(entity);
+        while let Some(e) = next {
+            //seen already means there's a loop further up that ancestor isn't part of
+            if e == ancestor || !seen.insert(e) {
+                return e == ancestor;
+            }
+            next = self.parent(e);
+        }
+        false
+    }
+    #[track_caller]
+    pub fn parent(&self, child: Entity) -> Option<Entity> {
+        self.clone::<Parent>(child).map(|Parent(p)| p)
+    }
+    #[track_caller]
+    pub fn children(&self, parent: Entity) -> Vec<Entity> {
+        self.clone::<Children>(parent).map_or(Vec::new(), |children| children.to_vec())
+    }
+
+    //secondary indices, see index.rs
+    //i.e. w.add_index::<RenderInfo, Sprites>(|r| r.0.clone()); ... for e in w.lookup::<RenderInfo, _>(&Sprites::Enemy) { ... }
+    pub fn add_index<C: Component, K: Eq + Hash + Clone + Send + Sync + 'static>(&mut self, key: fn(&C) -> K) {
+        self.register_component::<C>();
+        self.lock_write::<C>().add_index(key);
     }
-    //doing this in 'world' bc local copies of Entity might not be correct wrt hashing
-    pub fn type_of(&self, entity: Entity) -> i128 {
-        self.intern(entity).hash
+    //everything alive with that key, in the order they got it
+    #[track_caller]
+    pub fn lookup<C: Component, K: Eq + Hash + Clone + Send + Sync + 'static>(&self, key: &K) -> Vec<Entity> {
+        match self.try_lookup::<C, K>(key) {
+            Ok(entities) => entities,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    #[track_caller]
+    pub fn try_lookup<C: Component, K: Eq + Hash + Clone + Send + Sync + 'static>(&self, key: &K) -> Result<Vec<Entity>, Error> {
+        let indices = self.try_lock_read::<C>()?.lookup(key);
+        match indices {
+            Some(indices) => Ok(indices.into_iter().map(|i| self.entity_at(i)).filter(|&e| self.is_alive(e)).collect()),
+            None => Err(Error::MissingIndex(std::any::type_name::<C>(), std::any::type_name::<K>())),
+        }
+    }
+
+    //names, see names.rs; whoever got the name first, i.e. w.find_by_name("kay")
+    #[track_caller]
+    pub fn find_by_name(&self, name: &str) -> Option<Entity> {
+        self.entities_named(name).first().cloned()
+    }
+    #[track_caller]
+    pub fn entities_named(&self, name: &str) -> Vec<Entity> {
+        self.lookup::<Name, String>(&name.to_string())
     }
 
-    #[allow(unused)]
+    //clears out everything flagged Deleted and frees up their slots for create_entity
+    //bumping the generation is what invalidates any handles still floating around
     fn sweep_delete(&mut self) {
-        let mut deleted_entities: Vec<Entity> = Vec::new();
-        self.update_all(|e, _: &mut Deleted| {
-            deleted_entities.push(e);
-        });
-        for e in deleted_entities {
-            self.entities[e.index].generation += 1;
-            //TODO: enforce that entities of mismatched generations may not be accessed
+        //on_remove hooks can delete more entities (i.e. whatever was attached), so keep going until there's none left
+        loop {
+            //hooks can create entities too
+            self.entities.append(self.new_entities.get_mut().unwrap());
+            let deleted_entities: Vec<usize> = {
+                let lock = self.lock_read::<Deleted>();
+                lock.iter().map(|(i, _)| i).collect()
+            };
+            if deleted_entities.is_empty() {
+                break;
+            }
+            //hooks first, while these are all still in one piece
+            //the Deleted flag comes off so they still count as alive, i.e. w.clone() works on the rest of the entity
+            let tick = self.this_run();
+            for &i in &deleted_entities {
+                self.lock_write::<Deleted>().delete(self.entities[i], tick);
+            }
+            for &i in &deleted_entities {
+                let signature = self.signatures.get_mut().unwrap()[i].clone();
+                for id in signature.iter() {
+                    (self.remove_hooks[id])(self, self.entities[i]);
+                }
+            }
+            for &i in &deleted_entities {
+                let signature = std::mem::take(&mut self.signatures.get_mut().unwrap()[i]);
+                for id in signature.iter() {
+                    (self.deleters[id])(self, i);
+                }
+                self.entities[i].generation += 1;
+            }
+            self.free_entities.get_mut().unwrap().extend(deleted_entities);
         }
-        //TODO: reuse free entity slots
-        unimplemented!("cannot reuse entities yet");
     }
 
     //basic crud stuff
     //private bc systems should not operate on individual storages directly    
-    fn get_storage<C: Component>(&self) -> &RwLock<C::Storage> {
-        self.world[&TypeId::of::<C>()].downcast_ref::<RwLock<C::Storage>>().unwrap()
+    fn get_storage<C: Component>(&self) -> Result<&RwLock<Column<C>>, Error> {
+        match self.world.get(&TypeId::of::<C>()) {
+            Some(storage) => Ok(storage.downcast_ref::<RwLock<Column<C>>>().unwrap()),
+            None => Err(Error::UnregisteredComponent(std::any::type_name::<C>())),
+        }
     }
 
-    //these here should compare generations (with the entity in entities[])
-    pub fn insert<C: Component>(&self, mut entity: Entity, c: C) {
-        self.get_storage::<C>().write().unwrap().insert(entity.index, c);
-
-        if let Some(ref mut e) = self.updated_entities.borrow_mut().get_mut(entity.index) {
-            e.hash ^= self.hashes[&TypeId::of::<C>()];   
+    //these here compare generations (with the entity in entities[]) and ignore stale handles
+    #[track_caller]
+    pub fn insert<C: Component>(&self, entity: Entity, c: C) {
+        match self.try_insert(entity, c) {
+            Ok(()) | Err(Error::StaleEntity(_)) => (),
+            Err(e) => panic!("{}", e),
+        }
+    }
+    #[track_caller]
+    pub fn try_insert<C: Component>(&self, entity: Entity, c: C) -> Result<(), Error> {
+        if !self.is_current(entity) {
+            return Err(Error::StaleEntity(entity));
+        }
+        self.try_lock_read::<C>()?; //so a bad C fails here, before anything's happened
+        self.fire_on_replace::<C>(entity);
+        let tick = self.this_run();
+        self.try_lock_write::<C>()?.insert(entity.index, c, tick);
+        self.add_to_signature(entity.index, self.component_id::<C>());
+        self.fire_on_insert::<C>(entity);
+        Ok(())
+    }
+    //for putting back exactly what was there before (i.e. restoring a snapshot), where whatever the hooks
+    //would keep in sync is being put back too, i.e. Children along with Parent
+    #[track_caller]
+    pub fn insert_without_hooks<C: Component>(&self, entity: Entity, c: C) {
+        if !self.is_current(entity) {
             return;
         }
-        //if I try to do this in the 'else' it complains that updated_entities is already borrowed
-        //which, I mean, I guess
-        entity.hash ^= self.hashes[&TypeId::of::<C>()];
-        self.updated_entities.borrow_mut().push(entity);
+        let tick = self.this_run();
+        self.lock_write::<C>().insert(entity.index, c, tick);
+        self.add_to_signature(entity.index, self.component_id::<C>());
+    }
+    fn add_to_signature(&self, index: usize, id: usize) {
+        let added = {
+            let signature = &mut self.signatures.lock().unwrap()[index];
+            let added = !signature.contains(id);
+            signature.insert(id);
+            added
+        };
+        if added {
+            self.signature_changed(index);
+        }
     }
+    #[track_caller]
     pub fn delete<C: Component>(&self, entity: Entity) {
-        self.lock_write::<C>().delete(entity.index);
-        //todo: hashing
+        if !self.is_current(entity) {
+            return;
+        }
+        self.fire_on_remove::<C>(entity);
+        let tick = self.this_run();
+        self.lock_write::<C>().delete(entity, tick);
+        self.signatures.lock().unwrap()[entity.index].remove(self.component_id::<C>());
+        self.signature_changed(entity.index);
+    }
+
+    //the storage locks everything else goes through
+    //the try_ ones fail on unregistered components, and (in debug builds) on borrowing something this thread already has
+    #[track_caller]
+    pub(crate) fn try_lock_read<C: Component>(&self) -> Result<StorageRef<'_, Column<C>>, Error> {
+        let storage = self.get_storage::<C>()?;
+        let borrow = self.borrows.acquire(TypeId::of::<C>(), std::any::type_name::<C>(), false)?;
+        Ok(StorageRef::new(storage.read().unwrap(), borrow))
     }
-    fn lock_read<C: Component>(&self) -> impl Deref<Target=impl ComponentStorage<C>> + '_ {
-        self.get_storage::<C>().read().unwrap()
+    #[track_caller]
+    pub(crate) fn try_lock_write<C: Component>(&self) -> Result<StorageMut<'_, Column<C>>, Error> {
+        let storage = self.get_storage::<C>()?;
+        let borrow = self.borrows.acquire(TypeId::of::<C>(), std::any::type_name::<C>(), true)?;
+        Ok(StorageMut::new(storage.write().unwrap(), borrow))
     }
-    fn lock_write<C: Component>(&self) -> impl DerefMut<Target=impl ComponentStorage<C>> + '_ {
-        self.get_storage::<C>().write().unwrap()
+    #[track_caller]
+    pub(crate) fn lock_read<C: Component>(&self) -> StorageRef<'_, Column<C>> {
+        match self.try_lock_read::<C>() {
+            Ok(lock) => lock,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    #[track_caller]
+    pub(crate) fn lock_write<C: Component>(&self) -> StorageMut<'_, Column<C>> {
+        match self.try_lock_write::<C>() {
+            Ok(lock) => lock,
+            Err(e) => panic!("{}", e),
+        }
     }
     
     //returns copies, for simple value reading 
     //(also doesn't lock read for the length of the returned value)
+    #[track_caller]
     pub fn clone<C: Component>(&self, entity: Entity) -> Option<C> {
         if self.is_alive(entity) {
             self.lock_read::<C>().get(entity.index).cloned()
@@ -174,50 +613,176 @@ impl GameState {
         }
     }
     //unsafe function
+    #[track_caller]
     pub fn get_value<C: Component>(&self, entity: Entity) -> C {
         self.lock_read::<C>().get(entity.index).unwrap().clone()
     }
 
     //takes a closure, updates select components
+    #[track_caller]
     pub fn update_all<C: Component>(&self, mut f: impl FnMut(Entity, &mut C)) {
+        let tick = self.this_run();
         let mut lock = self.lock_write::<C>();
-        for i in 0..self.entities_size.load(Ordering::SeqCst) {
-            let e = Entity{index: i, generation: 0, hash: 0};
+        for (i, c) in lock.iter_mut_tracked(tick) {
+            let e = self.entity_at(i);
             if self.is_alive(e) {
-                if let Some(c) = lock.get_mut(e.index) {
-                    f(e, c);
-                }
+                f(e, c);
             }
         }
     }
-    pub fn update<C: Component>(&self, entity: Entity, mut f: impl FnMut(&mut C)) {
-        if self.is_alive(entity) {
-            if let Some(c) = self.lock_write::<C>().get_mut(entity.index) {
-                f(c);
-            }
+    #[track_caller]
+    pub fn update<C: Component>(&self, entity: Entity, f: impl FnMut(&mut C)) {
+        match self.try_update(entity, f) {
+            Ok(()) | Err(Error::StaleEntity(_)) | Err(Error::MissingComponent(_)) => (),
+            Err(e) => panic!("{}", e),
+        }
+    }
+    //same thing, but says why nothing happened, and passes back whatever f returns
+    #[track_caller]
+    pub fn try_update<C: Component, R>(&self, entity: Entity, f: impl FnOnce(&mut C) -> R) -> Result<R, Error> {
+        if !self.is_alive(entity) {
+            return Err(Error::StaleEntity(entity));
+        }
+        let tick = self.this_run();
+        match self.try_lock_write::<C>()?.get_mut_tracked(entity.index, tick) {
+            Some(c) => Ok(f(c)),
+            None => Err(Error::MissingComponent(std::any::type_name::<C>())),
         }
     }
 
+    #[track_caller]
     pub fn read_all<C: Component>(&self, mut f: impl FnMut(Entity, &C)) {
         let lock = self.lock_read::<C>();
-        for i in 0..self.entities_size.load(Ordering::SeqCst) {
-            let e = Entity{index: i, generation: 0, hash: 0};
+        for (i, c) in lock.iter() {
+            let e = self.entity_at(i);
             if self.is_alive(e) {
-                if let Some(c) = lock.get(e.index) {
-                    f(e, c);
+                f(e, c);
+            }
+        }
+    }
+    #[track_caller]
+    pub fn read<C: Component>(&self, entity: Entity, f: impl FnMut(&C)) {
+        match self.try_read(entity, f) {
+            Ok(()) | Err(Error::StaleEntity(_)) | Err(Error::MissingComponent(_)) => (),
+            Err(e) => panic!("{}", e),
+        }
+    }
+    #[track_caller]
+    pub fn try_read<C: Component, R>(&self, entity: Entity, f: impl FnOnce(&C) -> R) -> Result<R, Error> {
+        if !self.is_alive(entity) {
+            return Err(Error::StaleEntity(entity));
+        }
+        match self.try_lock_read::<C>()?.get(entity.index) {
+            Some(c) => Ok(f(c)),
+            None => Err(Error::MissingComponent(std::any::type_name::<C>())),
+        }
+    }
+
+    //hooks: run straight away, on whichever thread made the change, with the component as it was/is
+    //on_insert after every insert (new or not), on_replace right before an insert overwrites one, with the old value,
+    //on_remove right before delete::<C>() takes it off, or before a deleted entity gets swept
+    //(so in a Schedule, whatever a hook touches has to be covered by the access of any system that can set it off)
+    #[track_caller]
+    pub fn on_insert<C: Component>(&mut self, hook: Hook<C>) {
+        self.hooks_mut::<C>().on_insert.push(hook);
+    }
+    #[track_caller]
+    pub fn on_replace<C: Component>(&mut self, hook: Hook<C>) {
+        self.hooks_mut::<C>().on_replace.push(hook);
+    }
+    #[track_caller]
+    pub fn on_remove<C: Component>(&mut self, hook: Hook<C>) {
+        self.hooks_mut::<C>().on_remove.push(hook);
+    }
+    #[track_caller]
+    fn hooks_mut<C: Component>(&mut self) -> &mut Hooks<C> {
+        self.component_id::<C>();
+        self.hooks.entry(TypeId::of::<C>()).or_insert_with(|| Box::new(Hooks::<C>::new())).downcast_mut().unwrap()
+    }
+    fn hooks<C: Component>(&self) -> Option<&Hooks<C>> {
+        self.hooks.get(&TypeId::of::<C>()).map(|hooks| hooks.downcast_ref().unwrap())
+    }
+    //same, but for one that's about to be overwritten
+    pub(crate) fn fire_on_replace<C: Component>(&self, entity: Entity) {
+        if let Some(hooks) = self.hooks::<C>().filter(|hooks| !hooks.on_replace.is_empty()) {
+            let old = self.lock_read::<C>().get(entity.index).cloned();
+            if let Some(old) = old {
+                for hook in &hooks.on_replace {
+                    hook(self, entity, &old);
                 }
             }
         }
     }
-    pub fn read<C: Component>(&self, entity: Entity, mut f: impl FnMut(&C)) {
-        if self.is_alive(entity) {
-            if let Some(c) = self.lock_write::<C>().get(entity.index) {
-                f(c);
+    //and one that's just gone in
+    pub(crate) fn fire_on_insert<C: Component>(&self, entity: Entity) {
+        if let Some(hooks) = self.hooks::<C>().filter(|hooks| !hooks.on_insert.is_empty()) {
+            let new = self.lock_read::<C>().get(entity.index).cloned();
+            if let Some(new) = new {
+                for hook in &hooks.on_insert {
+                    hook(self, entity, &new);
+                }
+            }
+        }
+    }
+    //no-op if there aren't any hooks or the entity doesn't have one
+    fn fire_on_remove<C: Component>(&self, entity: Entity) {
+        if let Some(hooks) = self.hooks::<C>().filter(|hooks| !hooks.on_remove.is_empty()) {
+            let old = self.lock_read::<C>().get(entity.index).cloned();
+            if let Some(old) = old {
+                for hook in &hooks.on_remove {
+                    hook(self, entity, &old);
+                }
             }
         }
     }
 
+    //entities that lost a C since the current system last ran (or ever, outside of a Schedule)
+    //covers delete::<C>() and deleted entities both
+    #[track_caller]
+    pub fn removed<C: Component>(&self) -> impl Iterator<Item=Entity> {
+        let since = self.last_run();
+        self.lock_read::<C>().removed_since(since).collect::<Vec<_>>().into_iter()
+    }
+
+    //change ticks: every change gets stamped with this_run(), and Added/Changed/removed() look for stamps after last_run()
+    //inside a Schedule those are per system, so each one sees everything that happened since it last ran
+    pub(crate) fn this_run(&self) -> u64 {
+        match tracking::system_ticks() {
+            Some((_, this_run)) => this_run,
+            None => self.change_tick.fetch_add(1, Ordering::SeqCst) + 1,
+        }
+    }
+    pub(crate) fn last_run(&self) -> u64 {
+        tracking::system_ticks().map_or(0, |(last_run, _)| last_run)
+    }
+    pub(crate) fn run_system(&self, last_run: &mut u64, f: impl FnOnce(&GameState)) {
+        let this_run = self.change_tick.fetch_add(1, Ordering::SeqCst) + 1;
+        let outer = tracking::set_system_ticks(Some((*last_run, this_run)));
+        f(self);
+        tracking::set_system_ticks(outer);
+        *last_run = this_run;
+    }
+    //nobody's going to ask about removals at or before up_to anymore
+    pub(crate) fn forget_removed(&self, up_to: u64) {
+        for forget in &self.forgetters {
+            forget(self, up_to);
+        }
+    }
+
+    //for structural changes that have to wait until nothing's borrowed, see Commands
+    pub fn commands(&self) -> Commands<'_> {
+        Commands::new(self)
+    }
+
+    //borrows every storage in Q once, for iterating without cloning
+    //i.e. w.query::<(&mut Position, &Velocity)>().for_each(|(pos, vel)| { ... });
+    #[track_caller]
+    pub fn query<Q: Query>(&self) -> QueryBorrow<'_, Q> {
+        QueryBorrow::new(self)
+    }
+
     //just a simple check for flag-type components
+    #[track_caller]
     pub fn has_flag<C: Component>(&self, entity: Entity) -> bool {
         if self.is_alive(entity) {
             self.lock_read::<C>().get(entity.index).is_some()
@@ -228,6 +793,11 @@ impl GameState {
 }
 
 
+//for SystemRunner to find out who has a component without knowing its type
+fn indices_of<C: Component>(w: &GameState) -> Vec<usize> {
+    w.lock_read::<C>().iter().map(|(i, _)| i).collect()
+}
+
 //the big one
 //able to take a variadic number of &mut Components 
 //and run an arbitary function on ALL OF THEM
@@ -240,8 +810,11 @@ macro_rules! impl_system {
         impl<$($tp),*, Func> SystemRunner<($($tp),*,), Func> for GameState where $($tp: Component),*, Func: FnMut(($(&mut $tp),*,)) {
             #[allow(non_snake_case)] //required until rust has ident_lowercase! or smth
             fn run(&self, mut f: Func) {
-                for i in 0..self.entities_size.load(Ordering::SeqCst) {
-                    let e = Entity{index: i, generation: 0, hash: 0};
+                //only entities in the smallest storage can possibly have all of them
+                let storages = [$((self.lock_read::<$tp>().len(), indices_of::<$tp> as fn(&GameState) -> Vec<usize>)),*];
+                let (_, indices) = storages.iter().min_by_key(|(len, _)| *len).unwrap();
+                for i in indices(self) {
+                    let e = self.entity_at(i);
                     if self.is_alive(e) {
                         if let ($(Some(mut $tp)),*,) = ($(self.clone::<$tp>(e)),*,) {
                             //calls function on cloned values to make sure no concurrent access In the function
@@ -294,4 +867,167 @@ impl<T, U, V, F> SystemRunner<(T, U, V), F> for GameState where T: Component, U:
             }
         }
     }
-}*/
\ No newline at end of file
+}*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::VecStorage;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Counter(i32);
+    impl Component for Counter {
+        type Storage = VecStorage<Self>;
+    }
+
+    fn prepare_world() -> GameState {
+        let mut w = GameState::new();
+        w.register_component::<Counter>();
+        w
+    }
+
+    #[test]
+    fn reuse_deleted_slot() {
+        let mut w = prepare_world();
+        let a = w.create_entity();
+        let b = w.create_entity();
+        w.insert(a, Counter(1));
+        w.update_entities();
+        w.delete_entity(a);
+        assert!(w.is_deleted(a));
+        w.update_entities();
+        let c = w.create_entity();
+        //same slot, but the old handle is no longer valid
+        assert_eq!(c.id(), a.id());
+        assert_ne!(c, a);
+        assert!(w.is_alive(c));
+        assert!(!w.is_alive(a));
+        assert!(w.is_alive(b));
+        //sweeping cleared out the old components
+        assert_eq!(w.clone::<Counter>(c), None);
+    }
+
+    #[test]
+    fn stale_handles_rejected() {
+        let mut w = prepare_world();
+        let a = w.create_entity();
+        w.insert(a, Counter(1));
+        w.delete_entity(a);
+        w.update_entities();
+        let b = w.create_entity();
+        w.insert(b, Counter(2));
+        //none of these should touch b's data
+        w.insert(a, Counter(3));
+        w.update(a, |c: &mut Counter| c.0 = 4);
+        w.delete::<Counter>(a);
+        let mut read = false;
+        w.read(a, |_: &Counter| read = true);
+        assert!(!read);
+        assert_eq!(w.clone::<Counter>(a), None);
+        assert_eq!(w.clone::<Counter>(b), Some(Counter(2)));
+    }
+
+    #[test]
+    fn iteration_uses_current_handles() {
+        let mut w = prepare_world();
+        let a = w.create_entity();
+        w.delete_entity(a);
+        w.update_entities();
+        let b = w.create_entity();
+        w.insert(b, Counter(0));
+        w.update_all(|e, c: &mut Counter| {
+            assert_eq!(e, b);
+            c.0 += 1;
+        });
+        w.run(|(c,): (&mut Counter,)| c.0 += 1);
+        assert_eq!(w.clone::<Counter>(b), Some(Counter(2)));
+    }
+
+    #[derive(Clone)]
+    struct Tag<const A: usize, const B: usize>;
+    impl<const A: usize, const B: usize> Component for Tag<A, B> {
+        type Storage = NullStorage<Self>;
+    }
+
+    //registers every Tag<a, b> for a and b in the lists
+    macro_rules! register_tags {
+        ($w:expr; $($a:literal)*; $b:tt) => { $(register_tags!(@inner $w; $a; $b);)* };
+        (@inner $w:expr; $a:literal; [$($b:literal)*]) => { $($w.register_component::<Tag<$a, $b>>();)* };
+    }
+
+    #[test]
+    fn signature_tracks_components() {
+        let mut w = prepare_world();
+        register_tags!(w; 0 1 2 3 4 5 6 7 8 9 10 11; [0 1 2 3 4 5 6 7 8 9 10 11]);
+        let a = w.create_entity();
+        let b = w.create_entity();
+        w.insert(a, Tag::<11, 11>);
+        w.insert(a, Counter(0));
+        w.insert(b, Tag::<0, 0>);
+        w.insert(b, Counter(0));
+        assert_ne!(w.type_of(a), w.type_of(b));
+        assert!(w.has_component::<Tag<11, 11>>(a));
+        assert!(!w.has_component::<Tag<11, 11>>(b));
+        let names: Vec<_> = w.components_of(a).iter().map(|c| c.name).collect();
+        assert_eq!(names.len(), 2);
+        assert!(names[0].ends_with("Counter"));
+        w.delete::<Tag<11, 11>>(a);
+        w.insert(a, Tag::<0, 0>);
+        assert_eq!(w.type_of(a), w.type_of(b));
+        w.delete_entity(b);
+        w.update_entities();
+        assert!(w.type_of(w.create_entity()).is_empty());
+    }
+
+    #[derive(Clone)]
+    struct Unregistered;
+    impl Component for Unregistered {
+        type Storage = VecStorage<Self>;
+    }
+
+    #[test]
+    fn typed_errors() {
+        let mut w = prepare_world();
+        let a = w.create_entity();
+        assert_eq!(w.try_insert(a, Unregistered), Err(Error::UnregisteredComponent(std::any::type_name::<Unregistered>())));
+        assert_eq!(w.try_read(a, |_: &Counter| ()), Err(Error::MissingComponent(std::any::type_name::<Counter>())));
+        w.insert(a, Counter(1));
+        assert_eq!(w.try_update(a, |c: &mut Counter| { c.0 += 1; c.0 }), Ok(2));
+        w.delete_entity(a);
+        w.update_entities();
+        assert_eq!(w.try_insert(a, Counter(0)), Err(Error::StaleEntity(a)));
+        assert_eq!(w.try_read(a, |c: &Counter| c.0), Err(Error::StaleEntity(a)));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn reentrant_borrow_reported() {
+        let w = prepare_world();
+        let a = w.create_entity();
+        w.insert(a, Counter(0));
+        let mut result = Ok(());
+        w.update_all(|e, _: &mut Counter| result = w.try_insert(e, Counter(1)));
+        match result {
+            Err(Error::AlreadyBorrowed{component, held_at, wanted_at}) => {
+                assert!(component.ends_with("Counter"));
+                assert_eq!(held_at.file(), file!());
+                assert_eq!(wanted_at.line(), held_at.line());
+            }
+            other => panic!("expected AlreadyBorrowed, got {:?}", other),
+        }
+        //reading alongside a read is fine though, and the borrow's gone once update_all's done
+        w.read_all(|e, _: &Counter| assert_eq!(w.try_read(e, |c: &Counter| c.0), Ok(0)));
+        assert_eq!(w.try_insert(a, Counter(1)), Ok(()));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "already borrowed")]
+    fn reentrant_borrow_panics() {
+        let w = prepare_world();
+        let a = w.create_entity();
+        w.insert(a, Counter(0));
+        let mut q = w.query::<(&mut Counter,)>();
+        q.get(a, |_| w.insert(a, Counter(1)));
+    }
+}